@@ -1,116 +1,378 @@
+use crate::client::TwitchClient;
+use crate::error::{check_status, Error, Result};
+use crate::hls::{Cut, MasterPlaylist, MediaPlaylist, Variant};
+use crate::time;
 use futures::future;
-use lazy_static::lazy_static;
-use regex::Regex;
+use futures::stream::{self, StreamExt};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
 
-const OAUTH_TOKEN: &str = "jbun01lt3ul2yufhudh2m4m6ncokg3";
-const CLIENT_ID: &str = "kimne78kx3ncx6brgo4mv6wki5h1ko";
 const VODS_HASH: &str = "c3306aa37d92b24bc81a9b28dc64fca8232d53bc3072cd7038c71c0e704c0f58";
-const USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/81.0.4044.113 Safari/537.36";
-
-lazy_static! {
-    static ref SEGMENT_RE: Regex = Regex::new(
-        r#"#EXTINF:(?P<segmentDuration>[0-9]+\.[0-9]{3}),\n(?P<segmentNum>[0-9]+)\.ts"#,
-    )
-    .unwrap();
-}
+/// Most logins or IDs Helix accepts in a single request.
+const HELIX_BATCH: usize = 100;
+const PLAYBACK_ACCESS_TOKEN_HASH: &str =
+    "0828119ded1c13477966434e15800ff57ddacf13ba1911c129dc2200705b0712";
+/// Clips Helix returns for a time window before it stops paginating.
+const CLIPS_LIMIT: usize = 1000;
+/// Windows of clips aren't split below this many seconds.
+const CLIPS_MIN_WINDOW: i64 = 60;
+/// Seconds of VOD whose comments are fetched by a single task.
+const COMMENTS_RANGE: u64 = 600;
+const CLIP_ACCESS_TOKEN_HASH: &str =
+    "36b89d2507fce29e5ca551df756d27c1cfe079e2609642b4390aa4c35796eb11";
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct UserData {
-    id: String,
-    login: String,
-    display_name: String,
+    pub id: String,
+    pub login: String,
+    pub display_name: String,
     #[serde(rename(deserialize = "type"))]
-    user_type: String,
-    broadcaster_type: String,
-    description: String,
-    profile_image_url: String,
-    offline_image_url: String,
-    view_count: u64,
+    pub user_type: String,
+    pub broadcaster_type: String,
+    pub description: String,
+    pub profile_image_url: String,
+    pub offline_image_url: String,
+    pub view_count: u64,
+}
+
+/// A user to look up with `UserData::many`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum UserQuery {
+    Login(String),
+    Id(String),
+}
+
+impl UserQuery {
+    fn param(&self) -> (&str, &str) {
+        match self {
+            UserQuery::Login(login) => ("login", login),
+            UserQuery::Id(id) => ("id", id),
+        }
+    }
+}
+
+impl std::fmt::Display for UserQuery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UserQuery::Login(login) => write!(f, "login `{}`", login),
+            UserQuery::Id(id) => write!(f, "ID `{}`", id),
+        }
+    }
 }
 
 impl UserData {
-    pub async fn new(username: &str) -> Result<UserData, Box<dyn std::error::Error>> {
-        let client = reqwest::Client::new();
+    pub async fn new(client: &TwitchClient, username: &str) -> Result<UserData> {
+        let query = UserQuery::Login(username.to_string());
+        UserData::many(client, &[query])
+            .await?
+            .0
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::UnknownUser(username.to_string()))
+    }
+
+    /// Looks up users by login or ID, 100 at a time with the requests
+    /// running concurrently. Returns the users found, in the order Helix
+    /// returned them, and the queries that matched no user.
+    pub async fn many(
+        client: &TwitchClient,
+        queries: &[UserQuery],
+    ) -> Result<(Vec<UserData>, Vec<UserQuery>)> {
+        let chunks = future::try_join_all(
+            queries
+                .chunks(HELIX_BATCH)
+                .map(|chunk| Self::fetch(client, chunk)),
+        )
+        .await?;
+        let users = chunks.into_iter().flatten().collect::<Vec<_>>();
+        let logins = users
+            .iter()
+            .map(|user| user.login.to_lowercase())
+            .collect::<HashSet<_>>();
+        let ids = users
+            .iter()
+            .map(|user| user.id.as_str())
+            .collect::<HashSet<_>>();
+        let unknown = queries
+            .iter()
+            .filter(|query| match query {
+                UserQuery::Login(login) => !logins.contains(&login.to_lowercase()),
+                UserQuery::Id(id) => !ids.contains(id.as_str()),
+            })
+            .cloned()
+            .collect();
+        Ok((users, unknown))
+    }
+
+    /// Users matching at most `HELIX_BATCH` queries, in one request.
+    async fn fetch(client: &TwitchClient, queries: &[UserQuery]) -> Result<Vec<UserData>> {
+        let query = queries.iter().map(UserQuery::param).collect::<Vec<_>>();
+        let resp = check_status(client.helix("users").query(&query).send().await?)?
+            .json::<serde_json::Value>()
+            .await?;
+        let data = resp["data"]
+            .as_array()
+            .ok_or_else(|| Error::malformed("expected `data` to be an array"))?;
+        Ok(data
+            .iter()
+            .map(|user| serde_json::from_value(user.clone()))
+            .collect::<serde_json::Result<_>>()?)
+    }
 
-        Ok(serde_json::from_value(
+    pub async fn is_live(client: &TwitchClient, channel_name: &str) -> Result<bool> {
+        Ok(Stream::new(client, channel_name).await?.is_some())
+    }
+
+    /// Checks which of `channel_names` are live, with one request for every
+    /// 100 channels. If a request fails, every channel it covered gets its
+    /// error.
+    pub async fn are_live(
+        client: &TwitchClient,
+        channel_names: &[&str],
+    ) -> BTreeMap<String, LiveStatus> {
+        let chunks = future::join_all(
+            channel_names
+                .chunks(HELIX_BATCH)
+                .map(|chunk| async move { (chunk, Stream::fetch(client, chunk).await) }),
+        )
+        .await;
+
+        let mut statuses = BTreeMap::new();
+        for (chunk, result) in chunks {
+            match result {
+                Ok(streams) => {
+                    for name in chunk {
+                        let stream = streams
+                            .iter()
+                            .find(|stream| stream.user_login.eq_ignore_ascii_case(name));
+                        let status = match stream {
+                            Some(stream) => LiveStatus::Live(Box::new(stream.clone())),
+                            None => LiveStatus::Offline,
+                        };
+                        statuses.insert(name.to_string(), status);
+                    }
+                }
+                Err(err) => {
+                    let err = Arc::new(err);
+                    for name in chunk {
+                        statuses.insert(name.to_string(), LiveStatus::Error(err.clone()));
+                    }
+                }
+            }
+        }
+        statuses
+    }
+
+    /// Direct link to the master playlist of the live stream of a channel.
+    pub async fn fetch_live_link(client: &TwitchClient, channel_name: &str) -> Result<String> {
+        let resp = check_status(
             client
-                .get(&format!(
-                    "https://api.twitch.tv/helix/users?login={}",
-                    username
-                ))
-                .header("Client-Id", CLIENT_ID)
-                .header(reqwest::header::USER_AGENT, USER_AGENT)
-                .header(
-                    reqwest::header::AUTHORIZATION,
-                    &format!("Bearer {}", OAUTH_TOKEN),
-                )
+                .post(&format!("{}/gql", client.endpoints().gql))
+                .json(&serde_json::json!({
+                    "operationName": "PlaybackAccessToken",
+                    "variables": {
+                        "isLive": true,
+                        "login": channel_name,
+                        "isVod": false,
+                        "vodID": "",
+                        "playerType": "site",
+                    },
+                    "extensions": {
+                        "persistedQuery": {
+                            "version": 1,
+                            "sha256Hash": PLAYBACK_ACCESS_TOKEN_HASH,
+                        },
+                    },
+                }))
                 .send()
-                .await?
-                .json::<serde_json::Value>()
-                .await?["data"]
-                .as_array()
-                .ok_or(Box::<dyn std::error::Error>::from(
-                    "Twitch API was expected to return an array and didn't return one",
-                ))
-                .map(|x| x[0].clone())?,
-        )?)
+                .await?,
+        )?
+        .json::<serde_json::Value>()
+        .await?;
+
+        let access_token = &resp["data"]["streamPlaybackAccessToken"];
+        if access_token.is_null() {
+            return Err(Error::UnknownUser(channel_name.to_string()));
+        }
+        let sig = access_token["signature"]
+            .as_str()
+            .ok_or(Error::MissingAccessToken("signature"))?;
+        let token = access_token["value"]
+            .as_str()
+            .ok_or(Error::MissingAccessToken("value"))?;
+        let url = reqwest::Url::parse_with_params(
+            &format!(
+                "{}/api/channel/hls/{}.m3u8",
+                client.endpoints().usher,
+                channel_name.to_lowercase()
+            ),
+            &[
+                ("allow_source", "true"),
+                ("fast_bread", "true"),
+                ("player_backend", "mediaplayer"),
+                ("playlist_include_framerate", "true"),
+                ("reassignments_supported", "true"),
+                ("supported_codecs", "avc1"),
+                ("cdm", "wv"),
+                ("sig", sig),
+                ("token", token),
+            ],
+        )
+        .map_err(|_| Error::InvalidArgument(format!("invalid channel name `{}`", channel_name)))?;
+        Ok(url.to_string())
     }
 
-    pub async fn is_live(channel_name: &str) -> Result<bool, Box<dyn std::error::Error>> {
-        let client = reqwest::Client::new();
-        let resp = client
-            .get(&format!(
-                "https://api.twitch.tv/helix/streams?user_login={}",
-                channel_name
-            ))
-            .header(reqwest::header::ACCEPT, "application/vnd.twitchtv.v5+json")
-            .header(
-                reqwest::header::AUTHORIZATION,
-                &format!("Bearer {}", OAUTH_TOKEN),
-            )
-            .header("Client-Id", CLIENT_ID)
-            .send()
+    /// Fetches the master playlist of the live stream of a channel, which
+    /// lists its renditions. Fails with `Error::Offline` if the channel isn't
+    /// live.
+    pub async fn live_master_playlist(
+        client: &TwitchClient,
+        channel_name: &str,
+    ) -> Result<MasterPlaylist> {
+        let m3u8_link = Self::fetch_live_link(client, channel_name).await?;
+        match fetch_master_playlist(client, &m3u8_link).await {
+            Err(Error::NotFound(_)) => Err(Error::Offline(channel_name.to_string())),
+            result => result,
+        }
+    }
+
+    /// Direct link to the media playlist of the live rendition matching
+    /// `quality`, see `MasterPlaylist::select`.
+    pub async fn live_variant(
+        client: &TwitchClient,
+        channel_name: &str,
+        quality: &str,
+    ) -> Result<Variant> {
+        let master = Self::live_master_playlist(client, channel_name).await?;
+        Ok(master.select_or_err(quality)?.clone())
+    }
+}
+
+/// Whether a channel is live, see `UserData::are_live`.
+#[derive(Debug, Clone)]
+pub enum LiveStatus {
+    Live(Box<Stream>),
+    Offline,
+    /// The channel couldn't be checked. The error is shared by every channel
+    /// that was requested together with it.
+    Error(Arc<Error>),
+}
+
+impl LiveStatus {
+    pub fn is_live(&self) -> bool {
+        matches!(self, LiveStatus::Live(_))
+    }
+
+    pub fn stream(&self) -> Option<&Stream> {
+        match self {
+            LiveStatus::Live(stream) => Some(stream),
+            _ => None,
+        }
+    }
+}
+
+/// A live stream, as returned by `helix/streams`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Stream {
+    /// ID of the broadcast.
+    pub id: String,
+    pub user_id: String,
+    pub user_login: String,
+    pub user_name: String,
+    pub game_id: String,
+    pub game_name: String,
+    /// `live`, or an empty string if something went wrong on Twitch's side.
+    #[serde(rename = "type")]
+    pub stream_type: String,
+    pub title: String,
+    pub viewer_count: u64,
+    /// RFC 3339 date the broadcast started at.
+    pub started_at: String,
+    pub language: String,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub tags: Vec<String>,
+    /// URL of the preview, where `{width}` and `{height}` have to be replaced.
+    pub thumbnail_url: String,
+}
+
+fn null_as_default<'de, D, T>(deserializer: D) -> std::result::Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::deserialize(deserializer)?.unwrap_or_default())
+}
+
+impl Stream {
+    /// Current stream of a channel, `None` if it isn't live.
+    pub async fn new(client: &TwitchClient, channel_name: &str) -> Result<Option<Stream>> {
+        Ok(Self::many(client, &[channel_name])
             .await?
-            .text()
-            .await?;
-        let ch = resp.chars().collect::<Vec<_>>()[9];
-        return Ok(ch != ']');
+            .into_iter()
+            .next())
     }
 
-    pub async fn are_live(channel_names: &[&str]) -> Vec<bool> {
-        future::join_all(
+    /// Current streams of the channels that are live among `channel_names`.
+    /// Channels are requested 100 at a time, the most Helix allows, with the
+    /// requests running concurrently.
+    pub async fn many(client: &TwitchClient, channel_names: &[&str]) -> Result<Vec<Stream>> {
+        let chunks = future::try_join_all(
             channel_names
-                .into_iter()
-                .map(|user| Self::is_live(user))
-                .collect::<Vec<_>>(),
+                .chunks(HELIX_BATCH)
+                .map(|chunk| Self::fetch(client, chunk)),
         )
-        .await
-        .into_iter()
-        .filter_map(|x| x.ok())
-        .collect()
+        .await?;
+        Ok(chunks.into_iter().flatten().collect())
+    }
+
+    /// Current streams of at most `HELIX_BATCH` channels, in one request.
+    async fn fetch(client: &TwitchClient, channel_names: &[&str]) -> Result<Vec<Stream>> {
+        let query = channel_names
+            .iter()
+            .map(|name| ("user_login", *name))
+            .collect::<Vec<_>>();
+        let resp = check_status(client.helix("streams").query(&query).send().await?)?
+            .json::<serde_json::Value>()
+            .await?;
+        let data = resp["data"]
+            .as_array()
+            .ok_or_else(|| Error::malformed("expected `data` to be an array"))?;
+        Ok(data
+            .iter()
+            .map(|stream| serde_json::from_value(stream.clone()))
+            .collect::<serde_json::Result<_>>()?)
+    }
+
+    /// Time since the start of the broadcast.
+    pub fn uptime(&self) -> Option<Duration> {
+        let started_at = time::parse_rfc3339(&self.started_at)?;
+        let elapsed = time::now() as i64 - started_at;
+        Some(Duration::from_secs(elapsed.max(0) as u64))
     }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Chatters {
-    chatter_count: u64,
-    chatters: ChattersChatters,
+    pub chatter_count: u64,
+    pub chatters: ChattersChatters,
 }
 
 impl Chatters {
-    pub async fn new(username: &str) -> reqwest::Result<Chatters> {
-        let client = reqwest::Client::new();
-        client
-            .get(&format!(
-                "https://tmi.twitch.tv/group/user/{}/chatters",
-                username
-            ))
-            .send()
-            .await?
-            .json()
-            .await
+    pub async fn new(client: &TwitchClient, username: &str) -> Result<Chatters> {
+        Ok(check_status(
+            client
+                .get(&format!(
+                    "{}/group/user/{}/chatters",
+                    client.endpoints().tmi,
+                    username
+                ))
+                .send()
+                .await?,
+        )?
+        .json()
+        .await?)
     }
 
     pub fn is_online(&self, username: &str) -> bool {
@@ -124,41 +386,212 @@ impl Chatters {
             || self.chatters.viewers.contains(u)
     }
 
-    pub fn are_online<'a>(&self, usernames: &'a [&str]) -> Vec<bool> {
+    pub fn are_online(&self, usernames: &[&str]) -> Vec<bool> {
         usernames
-            .into_iter()
+            .iter()
             .map(|username| self.is_online(username))
             .collect::<Vec<_>>()
     }
+
+    /// Usernames of every chatter, whatever their role.
+    pub fn all(&self) -> impl Iterator<Item = &str> {
+        let c = &self.chatters;
+        c.broadcaster
+            .iter()
+            .chain(&c.vips)
+            .chain(&c.moderators)
+            .chain(&c.staff)
+            .chain(&c.admins)
+            .chain(&c.global_mods)
+            .chain(&c.viewers)
+            .map(|username| username.as_str())
+    }
 }
 
 #[derive(Serialize, Deserialize)]
-struct ChattersChatters {
-    broadcaster: Vec<String>,
-    vips: Vec<String>,
-    moderators: Vec<String>,
-    staff: Vec<String>,
-    admins: Vec<String>,
-    global_mods: Vec<String>,
-    viewers: Vec<String>,
+pub struct ChattersChatters {
+    pub broadcaster: Vec<String>,
+    pub vips: Vec<String>,
+    pub moderators: Vec<String>,
+    pub staff: Vec<String>,
+    pub admins: Vec<String>,
+    pub global_mods: Vec<String>,
+    pub viewers: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Clip {
-    id: String,
-    url: String,
-    embed_url: String,
-    broadcaster_id: String,
-    broadcaster_name: String,
-    creator_id: String,
-    creator_name: String,
-    video_id: String,
-    game_id: String,
-    language: String,
-    title: String,
-    view_count: u64,
-    created_at: String,
-    thumbnail_url: String,
+    /// Slug of the clip, as in `clips.twitch.tv/<id>`.
+    pub id: String,
+    pub url: String,
+    pub embed_url: String,
+    pub broadcaster_id: String,
+    pub broadcaster_name: String,
+    pub creator_id: String,
+    pub creator_name: String,
+    pub video_id: String,
+    pub game_id: String,
+    pub language: String,
+    pub title: String,
+    pub view_count: u64,
+    pub created_at: String,
+    pub thumbnail_url: String,
+}
+
+impl Clip {
+    pub async fn new(client: &TwitchClient, slug: &str) -> Result<Clip> {
+        let resp = check_status(client.helix("clips").query(&[("id", slug)]).send().await?)?
+            .json::<serde_json::Value>()
+            .await?;
+        let clip = resp["data"]
+            .as_array()
+            .ok_or_else(|| Error::malformed("expected `data` to be an array"))?
+            .first()
+            .ok_or_else(|| Error::UnknownClip(slug.to_string()))?;
+        Ok(serde_json::from_value(clip.clone())?)
+    }
+
+    /// Expands `{id}`, `{broadcaster}`, `{title}`, `{created_at}` and
+    /// `{quality}` in `template`. Characters that can't appear in file names
+    /// are replaced with `_`.
+    pub fn file_name(&self, template: &str, quality: &ClipQuality) -> String {
+        let sanitize = |value: &str| {
+            value
+                .chars()
+                .map(|c| match c {
+                    '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+                    c if c.is_control() => '_',
+                    c => c,
+                })
+                .collect::<String>()
+        };
+        template
+            .replace("{id}", &sanitize(&self.id))
+            .replace("{broadcaster}", &sanitize(&self.broadcaster_name))
+            .replace("{title}", &sanitize(&self.title))
+            .replace("{created_at}", &sanitize(&self.created_at))
+            .replace("{quality}", &quality.name())
+    }
+}
+
+/// A rendition of a clip.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClipQuality {
+    /// Height of the video, e.g. `720`.
+    pub quality: String,
+    #[serde(rename = "frameRate")]
+    pub frame_rate: f64,
+    #[serde(rename = "sourceURL")]
+    pub source_url: String,
+}
+
+impl ClipQuality {
+    /// Name of the rendition, in the format of the video groups of VODs
+    /// (e.g. `720p60`).
+    pub fn name(&self) -> String {
+        format!("{}p{}", self.quality, self.frame_rate.round())
+    }
+
+    fn height(&self) -> u64 {
+        self.quality.parse().unwrap_or(0)
+    }
+}
+
+/// The renditions of a clip, with the access token needed to download them.
+#[derive(Debug, Clone)]
+pub struct ClipMedia {
+    pub slug: String,
+    /// Sorted from best to worst.
+    pub qualities: Vec<ClipQuality>,
+    signature: String,
+    token: String,
+}
+
+impl ClipMedia {
+    pub async fn new(client: &TwitchClient, slug: &str) -> Result<ClipMedia> {
+        let resp = check_status(
+            client
+                .post(&format!("{}/gql", client.endpoints().gql))
+                .json(&serde_json::json!({
+                    "operationName": "VideoAccessToken_Clip",
+                    "variables": { "slug": slug },
+                    "extensions": {
+                        "persistedQuery": {
+                            "version": 1,
+                            "sha256Hash": CLIP_ACCESS_TOKEN_HASH,
+                        },
+                    },
+                }))
+                .send()
+                .await?,
+        )?
+        .json::<serde_json::Value>()
+        .await?;
+
+        let clip = &resp["data"]["clip"];
+        if clip.is_null() {
+            return Err(Error::UnknownClip(slug.to_string()));
+        }
+        let access_token = &clip["playbackAccessToken"];
+        let signature = access_token["signature"]
+            .as_str()
+            .ok_or(Error::MissingAccessToken("signature"))?;
+        let token = access_token["value"]
+            .as_str()
+            .ok_or(Error::MissingAccessToken("value"))?;
+        let mut qualities: Vec<ClipQuality> =
+            serde_json::from_value(clip["videoQualities"].clone())?;
+        qualities.sort_by(|a, b| {
+            (b.height(), b.frame_rate)
+                .partial_cmp(&(a.height(), a.frame_rate))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        Ok(ClipMedia {
+            slug: slug.to_string(),
+            qualities,
+            signature: signature.to_string(),
+            token: token.to_string(),
+        })
+    }
+
+    /// Picks a rendition. `selector` is a comma separated list of qualities
+    /// tried in order, each of which is either `best` (or `source`),
+    /// `worst` or the start of a rendition name (e.g. `720p60`, `480`).
+    pub fn select(&self, selector: &str) -> Option<&ClipQuality> {
+        selector
+            .split(',')
+            .map(|quality| quality.trim())
+            .filter_map(|quality| match quality {
+                "best" | "source" => self.qualities.first(),
+                "worst" => self.qualities.last(),
+                _ => self
+                    .qualities
+                    .iter()
+                    .find(|q| q.name().starts_with(quality)),
+            })
+            .next()
+    }
+
+    /// Like `select`, but fails listing the available renditions if none
+    /// matches.
+    pub fn select_or_err(&self, selector: &str) -> Result<&ClipQuality> {
+        self.select(selector).ok_or_else(|| {
+            Error::UnavailableQuality(
+                selector.to_string(),
+                self.qualities.iter().map(ClipQuality::name).collect(),
+            )
+        })
+    }
+
+    /// Link to the video of a rendition, signed with the access token.
+    pub fn url(&self, quality: &ClipQuality) -> Result<String> {
+        let url = reqwest::Url::parse_with_params(
+            &quality.source_url,
+            &[("sig", &self.signature), ("token", &self.token)],
+        )
+        .map_err(|_| Error::malformed("invalid clip source URL"))?;
+        Ok(url.to_string())
+    }
 }
 
 #[derive(Debug)]
@@ -169,156 +602,301 @@ pub struct Clips {
 
 impl Clips {
     pub async fn new(
+        client: &TwitchClient,
         username: &str,
         started_at: &str,
         ended_at: &str,
         cursor: Option<&str>,
-    ) -> Result<Clips, Box<dyn std::error::Error>> {
+    ) -> Result<Clips> {
         let broadcaster_id = {
-            let user_data = UserData::new(username).await?;
+            let user_data = UserData::new(client, username).await?;
             user_data.id
         };
+        Self::page(client, &broadcaster_id, started_at, ended_at, cursor).await
+    }
 
-        let client = reqwest::Client::new();
-        let resp = client
-            .get(&format!(
-                "https://api.twitch.tv/helix/clips?broadcaster_id={}&started_at={}&ended_at={}&after={}",
-                broadcaster_id,
-                started_at,
-                ended_at,
-                cursor.unwrap_or("").to_string()
-            ))
-            .header("Client-Id", CLIENT_ID)
-            .header(reqwest::header::USER_AGENT, USER_AGENT)
-            .header(
-                reqwest::header::AUTHORIZATION,
-                &format!("Bearer {}", OAUTH_TOKEN),
+    /// Every clip of `username` created between `started_at` and `ended_at`,
+    /// in seconds since the Unix epoch, sorted by creation date.
+    ///
+    /// Helix stops paginating after about 1000 clips, so windows with that
+    /// many clips are split in halves, fetched concurrently, until they are
+    /// small enough.
+    pub async fn all(
+        client: &TwitchClient,
+        username: &str,
+        started_at: i64,
+        ended_at: i64,
+    ) -> Result<Vec<Clip>> {
+        let broadcaster_id = UserData::new(client, username).await?.id;
+        let clips =
+            Self::window(client, &broadcaster_id, started_at, ended_at, CLIPS_LIMIT).await?;
+        Ok(Self::merge(clips))
+    }
+
+    /// Drops the clips listed twice by overlapping windows and sorts them by
+    /// creation date.
+    fn merge(clips: Vec<Clip>) -> Vec<Clip> {
+        let mut seen = HashSet::new();
+        let mut clips = clips
+            .into_iter()
+            .filter(|clip| seen.insert(clip.id.clone()))
+            .collect::<Vec<_>>();
+        clips.sort_by(|a, b| (&a.created_at, &a.id).cmp(&(&b.created_at, &b.id)));
+        clips
+    }
+
+    /// Clips of a window, split while they reach `limit`. May contain
+    /// duplicates.
+    fn window<'a>(
+        client: &'a TwitchClient,
+        broadcaster_id: &'a str,
+        started_at: i64,
+        ended_at: i64,
+        limit: usize,
+    ) -> future::BoxFuture<'a, Result<Vec<Clip>>> {
+        Box::pin(async move {
+            let (start, end) = (
+                time::format_rfc3339(started_at),
+                time::format_rfc3339(ended_at),
+            );
+            let mut clips = vec![];
+            let mut cursor = None;
+            loop {
+                let page =
+                    Self::page(client, broadcaster_id, &start, &end, cursor.as_deref()).await?;
+                clips.extend(page.clips);
+                cursor = page.cursor;
+                if cursor.is_none() || clips.len() >= limit {
+                    break;
+                }
+            }
+
+            if clips.len() < limit || ended_at - started_at <= CLIPS_MIN_WINDOW {
+                return Ok(clips);
+            }
+            let middle = started_at + (ended_at - started_at) / 2;
+            let (first, second) = future::try_join(
+                Self::window(client, broadcaster_id, started_at, middle, limit),
+                Self::window(client, broadcaster_id, middle, ended_at, limit),
             )
-            .send()
-            .await?
+            .await?;
+            Ok(first.into_iter().chain(second).collect())
+        })
+    }
+
+    async fn page(
+        client: &TwitchClient,
+        broadcaster_id: &str,
+        started_at: &str,
+        ended_at: &str,
+        cursor: Option<&str>,
+    ) -> Result<Clips> {
+        let mut query = vec![
+            ("broadcaster_id", broadcaster_id),
+            ("started_at", started_at),
+            ("ended_at", ended_at),
+            ("first", "100"),
+        ];
+        if let Some(cursor) = cursor {
+            query.push(("after", cursor));
+        }
+        let resp = check_status(client.helix("clips").query(&query).send().await?)?
             .json::<serde_json::Value>()
             .await?;
+        let data = resp["data"]
+            .as_array()
+            .ok_or_else(|| Error::malformed("expected `data` to be an array"))?;
 
         Ok(Clips {
             cursor: resp["pagination"]["cursor"].as_str().map(|x| x.to_string()),
-            clips: resp["data"]
-                .as_array()
-                .ok_or(Box::<dyn std::error::Error>::from(
-                    "Error decoding 'data', expecting Array, got something else",
-                ))?
-                .into_iter()
-                .filter_map(|item| serde_json::from_value(item.clone()).ok())
-                .collect(),
+            clips: data
+                .iter()
+                .map(|clip| serde_json::from_value(clip.clone()))
+                .collect::<serde_json::Result<_>>()?,
         })
     }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Vod {
-    id: String,
+    pub id: String,
     #[serde(rename(deserialize = "lengthSeconds"))]
-    length_seconds: u64,
+    pub length_seconds: u64,
     #[serde(rename(deserialize = "publishedAt"))]
-    published_at: String,
+    pub published_at: String,
     #[serde(rename(deserialize = "viewCount"))]
-    view_count: u64,
-    title: String,
+    pub view_count: u64,
+    pub title: String,
     #[serde(skip_deserializing)]
-    url: String,
+    pub url: String,
 }
 
-#[derive(Debug)]
-struct Segment {
-    uri: String,
-    duration: f64,
+#[derive(Debug, Clone)]
+pub struct Segment {
+    /// Number of the segment in the media playlist of the VOD.
+    pub number: u64,
+    pub uri: String,
+    pub duration: f64,
+}
+
+impl Segment {
+    /// Segments of `playlist`, numbered by their media sequence number.
+    pub fn from_playlist(playlist: &MediaPlaylist) -> Vec<Segment> {
+        playlist
+            .segments
+            .iter()
+            .zip(playlist.media_sequence..)
+            .map(|(segment, number)| Segment {
+                number,
+                uri: segment.uri.clone(),
+                duration: segment.duration,
+            })
+            .collect()
+    }
 }
 
 impl Vod {
-    pub async fn fetch_direct_link(vodid: &str) -> Result<String, Box<dyn std::error::Error>> {
-        let client = reqwest::Client::new();
-        let resp = client
-            .get(&format!("https://api.twitch.tv/api/vods/{}/access_token?need_https=true&oauth_token=&platform=_&player_backend=mediaplayer&player_type=site", vodid))
-            .header("Client-Id", CLIENT_ID)
-            .header(reqwest::header::USER_AGENT, USER_AGENT)
-            .send()
-            .await?
-            .json::<serde_json::Value>()
-            .await?;
+    pub fn length(&self) -> Duration {
+        Duration::from_secs(self.length_seconds)
+    }
 
-        let sig = resp["sig"].as_str().map(|x| x.to_string()).ok_or(
-            Box::<dyn std::error::Error>::from(
-                "Error decoding 'sig', expecting String, got something else",
-            ),
-        )?;
-        let token = resp["token"].as_str().map(|x| x.to_string()).ok_or(Box::<
-            dyn std::error::Error,
-        >::from(
-            "Error decoding 'token', expecting String, got something else",
-        ))?;
-        Ok(format!("https://usher.ttvnw.net/vod/{}.m3u8?allow_source=true&player_backend=mediaplayer&playlist_include_framerate=true&reassignments_supported=true&sig={}&supported_codecs=avc1&token={}&cdm=wv&player_version=0.9.8", vodid, sig, token))
+    pub async fn fetch_direct_link(client: &TwitchClient, vodid: &str) -> Result<String> {
+        let resp = check_status(
+            client
+                .get(&format!("{}/api/vods/{}/access_token?need_https=true&oauth_token=&platform=_&player_backend=mediaplayer&player_type=site", client.endpoints().api, vodid))
+                .send()
+                .await?,
+        )?
+        .json::<serde_json::Value>()
+        .await?;
+
+        let sig = resp["sig"]
+            .as_str()
+            .ok_or(Error::MissingAccessToken("sig"))?;
+        let token = resp["token"]
+            .as_str()
+            .ok_or(Error::MissingAccessToken("token"))?;
+        let url = reqwest::Url::parse_with_params(
+            &format!("{}/vod/{}.m3u8", client.endpoints().usher, vodid),
+            &[
+                ("allow_source", "true"),
+                ("player_backend", "mediaplayer"),
+                ("playlist_include_framerate", "true"),
+                ("reassignments_supported", "true"),
+                ("sig", sig),
+                ("supported_codecs", "avc1"),
+                ("token", token),
+                ("cdm", "wv"),
+                ("player_version", "0.9.8"),
+            ],
+        )
+        .map_err(|_| Error::InvalidArgument(format!("invalid VOD ID `{}`", vodid)))?;
+        Ok(url.to_string())
+    }
+
+    /// Fetches the master playlist of the VOD, which lists its renditions.
+    pub async fn master_playlist(client: &TwitchClient, vodid: &str) -> Result<MasterPlaylist> {
+        let m3u8_link = Self::fetch_direct_link(client, vodid).await?;
+        fetch_master_playlist(client, &m3u8_link).await
+    }
+
+    /// Direct link to the media playlist of the rendition matching `quality`,
+    /// see `MasterPlaylist::select`.
+    pub async fn variant(client: &TwitchClient, vodid: &str, quality: &str) -> Result<Variant> {
+        let master = Self::master_playlist(client, vodid).await?;
+        Ok(master.select_or_err(quality)?.clone())
     }
 
+    /// Media playlist of the rendition matching `quality`, with the URIs of
+    /// its segments resolved.
+    pub async fn media_playlist(
+        client: &TwitchClient,
+        vodid: &str,
+        quality: &str,
+    ) -> Result<MediaPlaylist> {
+        let uri = Self::variant(client, vodid, quality).await?.uri;
+        fetch_media_playlist(client, &uri).await
+    }
+
+    /// Media playlist of the rendition matching `quality`, trimmed to the
+    /// segments overlapping the range between `start` and `end` seconds, see
+    /// `MediaPlaylist::trim`.
+    pub async fn trimmed_playlist(
+        client: &TwitchClient,
+        vodid: &str,
+        quality: &str,
+        start: Option<f64>,
+        end: Option<f64>,
+    ) -> Result<(MediaPlaylist, Cut)> {
+        let mut playlist = Self::media_playlist(client, vodid, quality).await?;
+        let cut = playlist.trim(start, end)?;
+        Ok((playlist, cut))
+    }
+
+    /// Segments of the VOD overlapping the range between `start` and `end`
+    /// seconds, in the rendition matching `quality`.
+    pub async fn segments(
+        client: &TwitchClient,
+        vodid: &str,
+        quality: &str,
+        start: Option<f64>,
+        end: Option<f64>,
+    ) -> Result<Vec<Segment>> {
+        let (playlist, _) = Self::trimmed_playlist(client, vodid, quality, start, end).await?;
+        Ok(Segment::from_playlist(&playlist))
+    }
+
+    /// Media playlist with the segments of the VOD overlapping the range
+    /// between `start` and `end` seconds, in the rendition matching `quality`.
     pub async fn m3u8_gen(
+        client: &TwitchClient,
         vodid: &str,
+        quality: &str,
         start: Option<f64>,
         end: Option<f64>,
-    ) -> Result<String, Box<dyn std::error::Error>> {
-        let start = start.unwrap_or(0.0);
-        let end = end.unwrap_or(std::f64::INFINITY);
-
-        let m3u8_link = Self::fetch_direct_link(vodid).await?;
-        let m3u8_content = reqwest::get(&m3u8_link).await?.text().await?;
-        let direct_link = {
-            let parts = m3u8_content
-                .split("https://")
-                .nth(1)
-                .unwrap()
-                .split(".m3u8")
-                .nth(0)
-                .unwrap();
-            format!("https://{}.m3u8", parts)
-        };
-        let direct_link_parent = Path::new(&direct_link).parent().unwrap().to_str().unwrap();
-        let direct_link_m3u8_content = reqwest::get(&direct_link).await?.text().await?;
-
-        let segments = {
-            let segments: Vec<_> = SEGMENT_RE
-                .captures_iter(&direct_link_m3u8_content)
-                .into_iter()
-                .map(|caps| Segment {
-                    duration: caps["segmentDuration"].parse().unwrap(),
-                    uri: format!("{}/{}.ts", direct_link_parent, &caps["segmentNum"]),
-                })
-                .collect();
-
-            let mut duration_counter = 0.0;
-            let mut final_segments = vec![];
-            for segment in segments {
-                duration_counter += segment.duration;
-                if duration_counter >= start && duration_counter <= end {
-                    final_segments.push(segment);
-                } else if duration_counter > end {
-                    break;
-                }
-            }
-            final_segments
-        };
+    ) -> Result<String> {
+        let (mut playlist, _) = Self::trimmed_playlist(client, vodid, quality, start, end).await?;
+        playlist.end_list = true;
+        Ok(playlist.to_string())
+    }
+}
 
-        let mut final_m3u8_content = "
-#EXTM3U
-#EXT-X-VERSION:3
-#EXT-X-TARGETDURATION:12
-#EXT-X-PLAYLIST-TYPE:EVENT
-#EXT-X-MEDIA-SEQUENCE:0"
-            .trim_start()
+/// Fetches the master playlist at `url`, resolving the URIs of its variants
+/// against it.
+pub(crate) async fn fetch_master_playlist(
+    client: &TwitchClient,
+    url: &str,
+) -> Result<MasterPlaylist> {
+    let content = check_status(client.get(url).send().await?)?.text().await?;
+    let mut master = MasterPlaylist::parse(&content)?;
+    let base = reqwest::Url::parse(url)
+        .map_err(|_| Error::Playlist(format!("invalid playlist URL `{}`", url)))?;
+    for variant in &mut master.variants {
+        variant.uri = base
+            .join(&variant.uri)
+            .map_err(|_| Error::Playlist(format!("invalid variant URI `{}`", variant.uri)))?
+            .to_string();
+    }
+    Ok(master)
+}
+
+/// Fetches the media playlist at `url`, resolving the URIs of its segments
+/// against it.
+pub(crate) async fn fetch_media_playlist(
+    client: &TwitchClient,
+    url: &str,
+) -> Result<MediaPlaylist> {
+    let content = check_status(client.get(url).send().await?)?.text().await?;
+    let mut playlist = MediaPlaylist::parse(&content)?;
+    let base = reqwest::Url::parse(url)
+        .map_err(|_| Error::Playlist(format!("invalid playlist URL `{}`", url)))?;
+    for segment in &mut playlist.segments {
+        segment.uri = base
+            .join(&segment.uri)
+            .map_err(|_| Error::Playlist(format!("invalid segment URI `{}`", segment.uri)))?
             .to_string();
-        for segment in segments {
-            final_m3u8_content
-                .push_str(&format!("#EXTINF:{},\n{}\n", segment.duration, segment.uri));
-        }
-        final_m3u8_content.push_str("#EXT-X-ENDLIST");
-        Ok(final_m3u8_content)
     }
+    Ok(playlist)
 }
 
 #[derive(Debug)]
@@ -328,14 +906,9 @@ pub struct Vods {
 }
 
 impl Vods {
-    pub async fn new(
-        username: &str,
-        cursor: Option<&str>,
-    ) -> Result<Vods, Box<dyn std::error::Error>> {
-        let client = reqwest::Client::new();
-
+    pub async fn new(client: &TwitchClient, username: &str, cursor: Option<&str>) -> Result<Vods> {
         let resp = client
-            .post("https://gql.twitch.tv/gql")
+            .post(&format!("{}/gql", client.endpoints().gql))
             .body(format!(
                 "[{{\"operationName\":\"FilterableVideoTower_Videos\",\"variables\":{{\"limit\":30,\"channelOwnerLogin\":\"{}\",\"broadcastType\":\"ARCHIVE\",\"videoSort\":\"TIME\",\"cursor\":\"{}\"}},\"extensions\":{{\"persistedQuery\":{{\"version\":1,\"sha256Hash\":\"{}\"}}}}}}]",
                 username,
@@ -344,38 +917,31 @@ impl Vods {
             ))
             .header(reqwest::header::ACCEPT, "*/*")
             .header(reqwest::header::ACCEPT_LANGUAGE, "it-IT")
-            .header("Client-Id", CLIENT_ID)
             .header(reqwest::header::CONNECTION, "keep-alive")
             .header(reqwest::header::CONTENT_TYPE, "text/plain;charset=UTF-8")
-            .header(reqwest::header::HOST, "gql.twitch.tv")
             .header(reqwest::header::ORIGIN, "https://www.twitch.tv")
-            .header(reqwest::header::REFERER, &format!("https://www.twitch.tv/{}/videos?filter=archives&sort=time", username))
+            .header(reqwest::header::REFERER, format!("https://www.twitch.tv/{}/videos?filter=archives&sort=time", username))
             .header("Sec-Fetch-Dest", "empty")
             .header("Sec-Fetch-Mode", "cors")
             .header("Sec-Fetch-Site", "same-site")
-            .header(reqwest::header::USER_AGENT, USER_AGENT)
             .send()
-            .await?
-            .json::<serde_json::Value>()
             .await?;
+        let resp = check_status(resp)?.json::<serde_json::Value>().await?;
 
-        let user = resp.as_array().ok_or(Box::<dyn std::error::Error>::from(
-            "Twitch API was expected to return an array and didn't return one",
-        ))?[0]["data"]["user"]
-            .clone();
+        let user = &resp
+            .as_array()
+            .and_then(|resp| resp.first())
+            .ok_or_else(|| Error::malformed("expected a non-empty array"))?["data"]["user"];
         if user.is_null() {
-            return Err(Box::<dyn std::error::Error>::from("User doesn't exist"));
+            return Err(Error::UnknownUser(username.to_string()));
         }
-        let edges =
-            user["videos"]["edges"]
-                .as_array()
-                .ok_or(Box::<dyn std::error::Error>::from(
-                    "Twitch API was expected to return an array and didn't return one",
-                ))?;
+        let edges = user["videos"]["edges"]
+            .as_array()
+            .ok_or_else(|| Error::malformed("expected `videos.edges` to be an array"))?;
 
         let mut cursor: Option<String> = None;
         let vods: Vec<Vod> = edges
-            .into_iter()
+            .iter()
             .filter_map(|edge| {
                 if let Some(cur) = edge["cursor"].as_str() {
                     cursor = Some(cur.into());
@@ -394,32 +960,209 @@ impl Vods {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Comment {
-    created_at: String,
-    updated_at: String,
-    channel_id: String,
-    content_id: String,
-    content_offset_seconds: f64,
-    message: String,
-    user: CommentUser,
+    /// Empty for comments saved before it was recorded.
+    #[serde(default)]
+    pub id: String,
+    pub created_at: String,
+    pub updated_at: String,
+    pub channel_id: String,
+    pub content_id: String,
+    pub content_offset_seconds: f64,
+    pub message: String,
+    /// `message` split in text, emotes, mentions, cheers and links.
+    #[serde(default)]
+    pub fragments: Vec<Fragment>,
+    pub user: CommentUser,
+}
+
+impl Comment {
+    /// Offset of the comment from the start of the VOD.
+    pub fn offset(&self) -> Duration {
+        Duration::from_secs_f64(self.content_offset_seconds.max(0.0))
+    }
+
+    pub fn is_subscriber(&self) -> bool {
+        self.user
+            .badges
+            .iter()
+            .any(|badge| badge.id == "subscriber" || badge.id == "founder")
+    }
+}
+
+/// Where an emote comes from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum EmoteSource {
+    Twitch,
+    Bttv,
+    Ffz,
+    #[serde(rename = "7tv")]
+    SevenTv,
+}
+
+/// A part of a chat message.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Fragment {
+    Text {
+        text: String,
+    },
+    /// `begin` and `end` are the positions of the first and the last
+    /// character of the emote in the message, counted in chars.
+    Emote {
+        text: String,
+        id: String,
+        source: EmoteSource,
+        begin: usize,
+        end: usize,
+    },
+    /// `@login`
+    Mention {
+        text: String,
+        login: String,
+    },
+    /// `Cheer100` and the like, in messages that spent bits.
+    Cheer {
+        text: String,
+        prefix: String,
+        bits: u64,
+    },
+    Link {
+        text: String,
+        url: String,
+    },
+}
+
+impl Fragment {
+    pub fn text(&self) -> &str {
+        match self {
+            Fragment::Text { text }
+            | Fragment::Emote { text, .. }
+            | Fragment::Mention { text, .. }
+            | Fragment::Cheer { text, .. }
+            | Fragment::Link { text, .. } => text,
+        }
+    }
+
+    pub fn is_emote(&self) -> bool {
+        matches!(self, Fragment::Emote { .. })
+    }
+
+    /// Fragments of a message of the v5 API, where Twitch emotes are already
+    /// split from the text.
+    fn from_message(message: &serde_json::Value) -> Vec<Fragment> {
+        let cheers = message["bits_spent"].as_u64().unwrap_or(0) > 0;
+        let fragments = match message["fragments"].as_array() {
+            Some(fragments) => fragments,
+            None => return Self::parse_text(message["body"].as_str().unwrap_or(""), cheers),
+        };
+        let mut parsed = vec![];
+        let mut begin = 0;
+        for fragment in fragments {
+            let text = fragment["text"].as_str().unwrap_or("");
+            let length = text.chars().count();
+            match fragment["emoticon"]["emoticon_id"].as_str() {
+                Some(id) if length > 0 => parsed.push(Fragment::Emote {
+                    text: text.to_string(),
+                    id: id.to_string(),
+                    source: EmoteSource::Twitch,
+                    begin,
+                    end: begin + length - 1,
+                }),
+                _ => parsed.extend(Self::parse_text(text, cheers)),
+            }
+            begin += length;
+        }
+        Self::merge_text(parsed)
+    }
+
+    /// Splits plain text in text, mention, link and, if the message spent
+    /// bits, cheer fragments.
+    pub(crate) fn parse_text(text: &str, cheers: bool) -> Vec<Fragment> {
+        let mut fragments = vec![];
+        for word in text.split_inclusive(char::is_whitespace) {
+            let trimmed = word.trim_end();
+            let (word, space) = word.split_at(trimmed.len());
+            fragments.extend(Self::parse_word(word, cheers));
+            fragments.push(Fragment::Text {
+                text: space.to_string(),
+            });
+        }
+        Self::merge_text(fragments)
+    }
+
+    fn parse_word(word: &str, cheers: bool) -> Vec<Fragment> {
+        let text = |text: &str| Fragment::Text {
+            text: text.to_string(),
+        };
+        if word.starts_with("http://") || word.starts_with("https://") {
+            return vec![Fragment::Link {
+                text: word.to_string(),
+                url: word.to_string(),
+            }];
+        }
+        if let Some(login) = word.strip_prefix('@') {
+            let end = login
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(login.len());
+            if end > 0 {
+                let (login, rest) = login.split_at(end);
+                return vec![
+                    Fragment::Mention {
+                        text: format!("@{}", login),
+                        login: login.to_lowercase(),
+                    },
+                    text(rest),
+                ];
+            }
+        }
+        if cheers {
+            let prefix = word.trim_end_matches(|c: char| c.is_ascii_digit());
+            let bits = word[prefix.len()..].parse::<u64>().unwrap_or(0);
+            if bits > 0 && prefix.chars().any(|c| c.is_ascii_alphabetic()) {
+                return vec![Fragment::Cheer {
+                    text: word.to_string(),
+                    prefix: prefix.to_string(),
+                    bits,
+                }];
+            }
+        }
+        vec![text(word)]
+    }
+
+    /// Joins consecutive text fragments and drops empty ones.
+    pub(crate) fn merge_text(fragments: Vec<Fragment>) -> Vec<Fragment> {
+        let mut merged: Vec<Fragment> = vec![];
+        for fragment in fragments {
+            match (merged.last_mut(), fragment) {
+                (_, Fragment::Text { text }) if text.is_empty() => (),
+                (Some(Fragment::Text { text: last }), Fragment::Text { text }) => {
+                    last.push_str(&text)
+                }
+                (_, fragment) => merged.push(fragment),
+            }
+        }
+        merged
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-struct CommentUser {
-    display_name: String,
-    id: Option<String>,
-    username: String,
-    biography: Option<String>,
-    created_at: String,
-    updated_at: String,
-    profile_picture_url: String,
-    color: Option<String>,
-    badges: Vec<CommentUserBadge>,
+pub struct CommentUser {
+    pub display_name: String,
+    pub id: Option<String>,
+    pub username: String,
+    pub biography: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub profile_picture_url: String,
+    pub color: Option<String>,
+    pub badges: Vec<CommentUserBadge>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-struct CommentUserBadge {
-    id: String,
-    version: String,
+pub struct CommentUserBadge {
+    pub id: String,
+    pub version: String,
 }
 
 #[derive(Debug)]
@@ -429,39 +1172,148 @@ pub struct Comments {
 }
 
 impl Comments {
-    pub async fn new(
+    pub async fn new(client: &TwitchClient, vodid: &str, cursor: Option<&str>) -> Result<Comments> {
+        Self::fetch(client, vodid, "cursor", cursor.unwrap_or("")).await
+    }
+
+    /// Page of comments starting around `offset` seconds into the VOD. It
+    /// may start with some comments posted slightly earlier.
+    pub async fn at_offset(client: &TwitchClient, vodid: &str, offset: u64) -> Result<Comments> {
+        Self::fetch(client, vodid, "content_offset_seconds", &offset.to_string()).await
+    }
+
+    /// Every comment of the VOD, as one batch per range of `COMMENTS_RANGE`
+    /// seconds in the order of the VOD. Up to `parallelism` ranges are
+    /// fetched at once.
+    pub async fn all<'a>(
+        client: &'a TwitchClient,
+        vodid: &'a str,
+        parallelism: usize,
+    ) -> Result<impl stream::Stream<Item = Result<Vec<Comment>>> + 'a> {
+        let length = Self::vod_length(client, vodid).await?;
+        Ok(Self::in_ranges(
+            client,
+            vodid,
+            length,
+            COMMENTS_RANGE,
+            parallelism,
+        ))
+    }
+
+    fn in_ranges<'a>(
+        client: &'a TwitchClient,
+        vodid: &'a str,
+        length: u64,
+        range: u64,
+        parallelism: usize,
+    ) -> impl stream::Stream<Item = Result<Vec<Comment>>> + 'a {
+        // The last range is open, in case comments come after the length of
+        // the VOD.
+        let starts = (0..length.max(1))
+            .step_by(range as usize)
+            .collect::<Vec<_>>();
+        let ends = starts
+            .iter()
+            .skip(1)
+            .map(|end| Some(*end))
+            .chain(std::iter::once(None))
+            .collect::<Vec<_>>();
+        // Seeking returns some comments of the previous range as well.
+        let mut seen = HashSet::new();
+        stream::iter(starts.into_iter().zip(ends))
+            .map(move |(start, end)| Self::range(client, vodid, start, end))
+            .buffered(parallelism.max(1))
+            .map(move |comments| {
+                let mut comments = comments?;
+                comments.retain(|comment| comment.id.is_empty() || seen.insert(comment.id.clone()));
+                Ok(comments)
+            })
+    }
+
+    /// Comments posted between `start` and `end` seconds into the VOD,
+    /// sorted.
+    async fn range(
+        client: &TwitchClient,
         vodid: &str,
-        cursor: Option<&str>,
-    ) -> Result<Comments, Box<dyn std::error::Error>> {
-        let client = reqwest::Client::new();
-        let resp = client
-            .get(&format!(
-                "https://api.twitch.tv/v5/videos/{}/comments?client_id={}&cursor={}",
-                vodid,
-                CLIENT_ID,
-                cursor.unwrap_or("")
-            ))
-            .header(reqwest::header::USER_AGENT, USER_AGENT)
-            .send()
-            .await?
-            .json::<serde_json::Value>()
-            .await?;
+        start: u64,
+        end: Option<u64>,
+    ) -> Result<Vec<Comment>> {
+        let before_end = |comment: &Comment| match end {
+            Some(end) => comment.content_offset_seconds < end as f64,
+            None => true,
+        };
+        let mut page = Self::at_offset(client, vodid, start).await?;
+        let mut comments = vec![];
+        loop {
+            let done = matches!(page.comments.last(), Some(last) if !before_end(last));
+            comments.extend(page.comments.into_iter().filter(|comment| {
+                comment.content_offset_seconds >= start as f64 && before_end(comment)
+            }));
+            match page.cursor {
+                Some(cursor) if !done => page = Self::new(client, vodid, Some(&cursor)).await?,
+                _ => break,
+            }
+        }
+        comments.sort_by(|a, b| {
+            a.content_offset_seconds
+                .partial_cmp(&b.content_offset_seconds)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        Ok(comments)
+    }
+
+    /// Length of the VOD in seconds.
+    async fn vod_length(client: &TwitchClient, vodid: &str) -> Result<u64> {
+        let resp = check_status(
+            client
+                .get(&format!(
+                    "{}/v5/videos/{}?client_id={}",
+                    client.endpoints().api,
+                    vodid,
+                    client.credentials().client_id
+                ))
+                .send()
+                .await?,
+        )?
+        .json::<serde_json::Value>()
+        .await?;
+        resp["length"]
+            .as_u64()
+            .ok_or_else(|| Error::malformed("expected `length` to be a number"))
+    }
+
+    async fn fetch(client: &TwitchClient, vodid: &str, key: &str, value: &str) -> Result<Comments> {
+        let resp = check_status(
+            client
+                .get(&format!(
+                    "{}/v5/videos/{}/comments?client_id={}&{}={}",
+                    client.endpoints().api,
+                    vodid,
+                    client.credentials().client_id,
+                    key,
+                    value
+                ))
+                .send()
+                .await?,
+        )?
+        .json::<serde_json::Value>()
+        .await?;
 
         let cursor = resp["_next"].as_str().map(|x| x.to_string());
         let comments = resp["comments"]
             .as_array()
-            .ok_or(Box::<dyn std::error::Error>::from(
-                "Twitch API was expected to return an array and didn't return one",
-            ))?
-            .into_iter()
+            .ok_or_else(|| Error::malformed("expected `comments` to be an array"))?
+            .iter()
             .filter_map(|comment| {
                 Some(Comment {
+                    id: comment["_id"].as_str().unwrap_or_default().to_string(),
                     created_at: comment["created_at"].as_str()?.to_string(),
                     updated_at: comment["updated_at"].as_str()?.to_string(),
                     channel_id: comment["channel_id"].as_str()?.to_string(),
                     content_id: comment["content_id"].as_str()?.to_string(),
                     content_offset_seconds: comment["content_offset_seconds"].as_f64()?,
                     message: comment["message"]["body"].as_str()?.to_string(),
+                    fragments: Fragment::from_message(&comment["message"]),
                     user: CommentUser {
                         color: comment["message"]["user_color"]
                             .as_str()
@@ -477,7 +1329,7 @@ impl Comments {
                             .as_array()
                             .map(|badges| {
                                 badges
-                                    .into_iter()
+                                    .iter()
                                     .filter_map(|badge| {
                                         Some(CommentUserBadge {
                                             id: badge["_id"].as_str()?.to_string(),
@@ -486,7 +1338,7 @@ impl Comments {
                                     })
                                     .collect()
                             })
-                            .unwrap_or(Vec::new()),
+                            .unwrap_or_default(),
                     },
                 })
             })
@@ -495,85 +1347,822 @@ impl Comments {
     }
 }
 
+/// Images of the chat badges usable in a channel, by set and version
+/// (e.g. `subscriber` and `12`).
+#[derive(Debug, Default)]
+pub struct ChatBadges {
+    pub images: BTreeMap<(String, String), String>,
+}
+
+impl ChatBadges {
+    /// Global badges and the ones of the channel with ID `broadcaster_id`,
+    /// which win over the global ones.
+    pub async fn new(client: &TwitchClient, broadcaster_id: &str) -> Result<ChatBadges> {
+        let (global, channel) = future::try_join(
+            Self::fetch(client.helix("chat/badges/global")),
+            Self::fetch(
+                client
+                    .helix("chat/badges")
+                    .query(&[("broadcaster_id", broadcaster_id)]),
+            ),
+        )
+        .await?;
+        let mut images = global;
+        images.extend(channel);
+        Ok(ChatBadges { images })
+    }
+
+    async fn fetch(request: reqwest::RequestBuilder) -> Result<BTreeMap<(String, String), String>> {
+        let resp = check_status(request.send().await?)?
+            .json::<serde_json::Value>()
+            .await?;
+        let sets = resp["data"]
+            .as_array()
+            .ok_or_else(|| Error::malformed("expected `data` to be an array"))?;
+        let mut images = BTreeMap::new();
+        for set in sets {
+            let set_id = match set["set_id"].as_str() {
+                Some(set_id) => set_id,
+                None => continue,
+            };
+            for version in set["versions"].as_array().into_iter().flatten() {
+                if let (Some(id), Some(url)) =
+                    (version["id"].as_str(), version["image_url_1x"].as_str())
+                {
+                    images.insert((set_id.to_string(), id.to_string()), url.to_string());
+                }
+            }
+        }
+        Ok(images)
+    }
+
+    /// URL of the image of a badge.
+    pub fn image(&self, badge: &CommentUserBadge) -> Option<&str> {
+        self.images
+            .get(&(badge.id.clone(), badge.version.clone()))
+            .map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_support {
+    use super::*;
+
+    /// Builder of the comments used in tests.
+    pub(crate) struct CommentBuilder(Comment);
+
+    /// A comment of Alice at the start of the VOD, without badges or text.
+    pub(crate) fn comment() -> CommentBuilder {
+        CommentBuilder(Comment {
+            id: String::new(),
+            created_at: String::new(),
+            updated_at: String::new(),
+            channel_id: "1".to_string(),
+            content_id: "2".to_string(),
+            content_offset_seconds: 0.0,
+            message: String::new(),
+            fragments: vec![],
+            user: CommentUser {
+                display_name: "Alice".to_string(),
+                id: None,
+                username: "alice".to_string(),
+                biography: None,
+                created_at: String::new(),
+                updated_at: String::new(),
+                profile_picture_url: String::new(),
+                color: None,
+                badges: vec![],
+            },
+        })
+    }
+
+    impl CommentBuilder {
+        pub(crate) fn offset(mut self, offset: f64) -> Self {
+            self.0.content_offset_seconds = offset;
+            self
+        }
+
+        /// Sets the display name, and the login as its lowercase.
+        pub(crate) fn name(mut self, name: &str) -> Self {
+            self.0.user.display_name = name.to_string();
+            self.0.user.username = name.to_lowercase();
+            self
+        }
+
+        /// Sets the message, without fragments.
+        pub(crate) fn message(mut self, message: &str) -> Self {
+            self.0.message = message.to_string();
+            self.0.fragments = vec![];
+            self
+        }
+
+        /// Sets the fragments, and the message to their text.
+        pub(crate) fn fragments(mut self, fragments: Vec<Fragment>) -> Self {
+            self.0.message = fragments.iter().map(Fragment::text).collect();
+            self.0.fragments = fragments;
+            self
+        }
+
+        pub(crate) fn build(self) -> Comment {
+            self.0
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock::{MockServer, Route};
     use tokio::runtime::Runtime;
 
+    const USERS: &str = r#"{"data":[{"id":"123","login":"dariomocciatwitch","display_name":"DarioMocciaTwitch","type":"","broadcaster_type":"partner","description":"","profile_image_url":"https://example.com/p.png","offline_image_url":"","view_count":42}]}"#;
+    const ACCESS_TOKEN: &str = r#"{"token":"{\"vod_id\":596966295}","sig":"abcdef"}"#;
+    const STREAMS: &str = r#"{"data":[{"id":"40952121085","user_id":"123","user_login":"live","user_name":"Live","game_id":"509658","game_name":"Just Chatting","type":"live","title":"hello","viewer_count":78365,"started_at":"2020-05-01T18:00:00Z","language":"it","thumbnail_url":"https://static-cdn.jtvnw.net/previews-ttv/live_user_live-{width}x{height}.jpg","tag_ids":[],"tags":null,"is_mature":false}],"pagination":{}}"#;
+    const PLAYBACK_ACCESS_TOKEN: &str = r#"{"data":{"streamPlaybackAccessToken":{"value":"{\"channel\":\"dariomocciatwitch\"}","signature":"abcdef"}}}"#;
+    const MASTER_PLAYLIST: &str = "#EXTM3U
+#EXT-X-MEDIA:TYPE=VIDEO,GROUP-ID=\"chunked\",NAME=\"1080p60 (source)\",AUTOSELECT=YES,DEFAULT=YES
+#EXT-X-STREAM-INF:BANDWIDTH=6000000,RESOLUTION=1920x1080,CODECS=\"avc1.64002A,mp4a.40.2\",VIDEO=\"chunked\",FRAME-RATE=60.000
+{base}/vod/chunked/index-dvr.m3u8
+";
+    const MEDIA_PLAYLIST: &str = "#EXTM3U
+#EXT-X-VERSION:3
+#EXT-X-TARGETDURATION:10
+#EXTINF:10.000,
+0.ts
+#EXTINF:10.000,
+1-muted.ts
+#EXTINF:9.500,
+2.ts
+#EXTINF:4.500,
+3.ts
+#EXT-X-ENDLIST
+";
+
+    const USERS_EMPTY: &str = r#"{"data":[]}"#;
+
     #[test]
     fn user_data() {
         let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
-        let user_data = runtime.block_on(UserData::new("dariomocciatwitch"));
+        let user_data = runtime
+            .block_on(async {
+                let server = MockServer::start(vec![Route::new("/helix/users", USERS)]).await;
+                UserData::new(&server.client(), "dariomocciatwitch").await
+            })
+            .unwrap();
+        assert_eq!(user_data.id, "123");
+        assert_eq!(user_data.display_name, "DarioMocciaTwitch");
+        assert_eq!(user_data.view_count, 42);
+    }
+
+    #[test]
+    fn many_users() {
+        let mut queries = vec![
+            UserQuery::Login("DarioMocciaTwitch".to_string()),
+            UserQuery::Id("7".to_string()),
+        ];
+        queries.extend((0..150).map(|i| UserQuery::Login(format!("u{}", i))));
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let (users, unknown) = runtime
+            .block_on(async {
+                let server = MockServer::start(vec![
+                    Route::new("/helix/users?login=DarioMocciaTwitch&id=7&", USERS),
+                    Route::new("/helix/users?login=u98&", USERS_EMPTY),
+                ])
+                .await;
+                UserData::many(&server.client(), &queries).await
+            })
+            .unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].login, "dariomocciatwitch");
+        assert_eq!(unknown.len(), 151);
+        assert_eq!(unknown[0], UserQuery::Id("7".to_string()));
+        assert_eq!(unknown[150], UserQuery::Login("u149".to_string()));
+    }
+
+    #[test]
+    fn unknown_user_data() {
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let user_data = runtime.block_on(async {
+            let server =
+                MockServer::start(vec![Route::new("/helix/users", r#"{"data":[]}"#)]).await;
+            UserData::new(&server.client(), "nobody").await
+        });
         match user_data {
-            Ok(data) => println!("{:#?}", data),
-            Err(_) => {
-                user_data.unwrap();
+            Err(Error::UnknownUser(username)) => assert_eq!(username, "nobody"),
+            other => panic!("expected UnknownUser, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn http_errors() {
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let (unauthorized, missing_sig, not_found) = runtime.block_on(async {
+            let server = MockServer::start(vec![
+                Route::with_status("/helix/users", 401, r#"{"status":401}"#),
+                Route::new("/api/vods/1/access_token", r#"{"token":"t"}"#),
+            ])
+            .await;
+            let client = server.client();
+            (
+                UserData::new(&client, "someone").await,
+                Vod::fetch_direct_link(&client, "1").await,
+                Vod::fetch_direct_link(&client, "2").await,
+            )
+        });
+        assert_eq!(unauthorized.unwrap_err().exit_code(), 4);
+        assert!(matches!(missing_sig, Err(Error::MissingAccessToken("sig"))));
+        assert!(matches!(not_found, Err(Error::NotFound(_))));
+    }
+
+    #[test]
+    fn are_live() {
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let are_live = runtime.block_on(async {
+            let server = MockServer::start(vec![
+                Route::new("/helix/streams?user_login=live", STREAMS),
+                Route::new("/helix/streams", r#"{"data":[],"pagination":{}}"#),
+            ])
+            .await;
+            let client = server.client();
+            (
+                UserData::are_live(&client, &["live", "offline"]).await,
+                client.is_live("live").await.unwrap(),
+            )
+        });
+        let (statuses, is_live) = are_live;
+        assert!(is_live);
+        assert_eq!(statuses.len(), 2);
+        assert_eq!(
+            statuses["live"].stream().map(|stream| stream.id.as_str()),
+            Some("40952121085")
+        );
+        assert!(matches!(statuses["offline"], LiveStatus::Offline));
+    }
+
+    #[test]
+    fn are_live_batches() {
+        let mut names = vec!["live".to_string()];
+        names.extend((1..150).map(|i| format!("c{}", i)));
+        let names = names.iter().map(|name| name.as_str()).collect::<Vec<_>>();
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let statuses = runtime.block_on(async {
+            let server = MockServer::start(vec![
+                Route::with_status("/helix/streams?user_login=c100&", 500, ""),
+                Route::new("/helix/streams?user_login=live&user_login=c1&", STREAMS),
+            ])
+            .await;
+            UserData::are_live(&server.client(), &names).await
+        });
+        assert_eq!(statuses.len(), 150);
+        assert!(statuses["live"].is_live());
+        assert!(matches!(statuses["c99"], LiveStatus::Offline));
+        for name in &["c100", "c149"] {
+            match &statuses[*name] {
+                LiveStatus::Error(err) => assert!(matches!(**err, Error::Server(500, _))),
+                other => panic!("expected Error, got {:?}", other),
             }
         }
     }
 
+    #[test]
+    fn streams() {
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let streams = runtime
+            .block_on(async {
+                let server = MockServer::start(vec![Route::new(
+                    "/helix/streams?user_login=live&user_login=offline",
+                    STREAMS,
+                )])
+                .await;
+                Stream::many(&server.client(), &["live", "offline"]).await
+            })
+            .unwrap();
+        assert_eq!(streams.len(), 1);
+        let stream = &streams[0];
+        assert_eq!(stream.id, "40952121085");
+        assert_eq!(stream.game_name, "Just Chatting");
+        assert_eq!(stream.stream_type, "live");
+        assert_eq!(stream.viewer_count, 78365);
+        assert!(stream.tags.is_empty());
+        assert!(stream.uptime().unwrap() > Duration::from_secs(86400));
+    }
+
+    #[test]
+    fn chatters() {
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let chatters = runtime
+            .block_on(async {
+                let server = MockServer::start(vec![Route::new(
+                    "/group/user/streamer/chatters",
+                    r#"{"chatter_count":2,"chatters":{"broadcaster":["streamer"],"vips":[],"moderators":[],"staff":[],"admins":[],"global_mods":[],"viewers":["viewer"]}}"#,
+                )])
+                .await;
+                Chatters::new(&server.client(), "streamer").await
+            })
+            .unwrap();
+        assert_eq!(
+            chatters.are_online(&["streamer", "viewer", "nobody"]),
+            vec![true, true, false]
+        );
+        assert_eq!(
+            chatters.all().collect::<Vec<_>>(),
+            vec!["streamer", "viewer"]
+        );
+    }
+
     #[test]
     fn vod_direct_link() {
         let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
-        let vod_direct_link = runtime.block_on(Vod::fetch_direct_link("596966295"));
-        match vod_direct_link {
-            Ok(dlink) => println!("{}", dlink),
-            Err(_) => {
-                vod_direct_link.unwrap();
-            }
+        let (dlink, base) = runtime.block_on(async {
+            let server = MockServer::start(vec![Route::new(
+                "/api/vods/596966295/access_token",
+                ACCESS_TOKEN,
+            )])
+            .await;
+            (
+                Vod::fetch_direct_link(&server.client(), "596966295").await,
+                server.url(),
+            )
+        });
+        let dlink = dlink.unwrap();
+        assert!(dlink.starts_with(&format!("{}/vod/596966295.m3u8?", base)));
+        assert!(dlink.contains("&sig=abcdef&"));
+        assert!(dlink.contains("&token=%7B%22vod_id%22%3A596966295%7D&"));
+    }
+
+    #[test]
+    fn live_link() {
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let (variant, base) = runtime.block_on(async {
+            let server = MockServer::start(vec![
+                Route::new("/gql", PLAYBACK_ACCESS_TOKEN),
+                Route::new("/api/channel/hls/dariomocciatwitch.m3u8", MASTER_PLAYLIST),
+            ])
+            .await;
+            (
+                UserData::live_variant(&server.client(), "DarioMocciaTwitch", "source").await,
+                server.url(),
+            )
+        });
+        assert_eq!(
+            variant.unwrap().uri,
+            format!("{}/vod/chunked/index-dvr.m3u8", base)
+        );
+
+        let link = runtime.block_on(async {
+            let server = MockServer::start(vec![Route::new("/gql", PLAYBACK_ACCESS_TOKEN)]).await;
+            UserData::fetch_live_link(&server.client(), "dariomocciatwitch").await
+        });
+        let link = link.unwrap();
+        assert!(link.contains("/api/channel/hls/dariomocciatwitch.m3u8?"));
+        assert!(link.contains("&sig=abcdef&token=%7B%22channel%22%3A%22dariomocciatwitch%22%7D"));
+    }
+
+    #[test]
+    fn offline_or_unknown_channel() {
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let offline = runtime.block_on(async {
+            let server = MockServer::start(vec![Route::new("/gql", PLAYBACK_ACCESS_TOKEN)]).await;
+            UserData::live_master_playlist(&server.client(), "dariomocciatwitch").await
+        });
+        match offline {
+            Err(Error::Offline(channel)) => assert_eq!(channel, "dariomocciatwitch"),
+            other => panic!("expected Offline, got {:?}", other),
+        }
+
+        let unknown = runtime.block_on(async {
+            let server = MockServer::start(vec![Route::new(
+                "/gql",
+                r#"{"data":{"streamPlaybackAccessToken":null}}"#,
+            )])
+            .await;
+            UserData::fetch_live_link(&server.client(), "nobody").await
+        });
+        match unknown {
+            Err(Error::UnknownUser(username)) => assert_eq!(username, "nobody"),
+            other => panic!("expected UnknownUser, got {:?}", other),
         }
     }
 
     #[test]
     fn clips() {
         let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
-        let clips = runtime.block_on(Clips::new(
-            "dariomocciatwitch",
-            "2020-04-18T19:35:06Z",
-            "2020-04-22T19:35:06Z",
-            None,
-        ));
-        match clips {
-            Ok(c) => println!("{:#?}", c),
-            Err(_) => {
-                clips.unwrap();
-            }
+        let clips = runtime
+            .block_on(async {
+                let server = MockServer::start(vec![
+                    Route::new("/helix/users", USERS),
+                    Route::new(
+                        "/helix/clips?broadcaster_id=123&started_at=2020-04-18T21%3A35%3A06%2B02%3A00&ended_at=2020-04-22T19%3A35%3A06Z&first=100",
+                        r#"{"data":[{"id":"FunnyClip","url":"https://clips.twitch.tv/FunnyClip","embed_url":"https://clips.twitch.tv/embed?clip=FunnyClip","broadcaster_id":"123","broadcaster_name":"DarioMocciaTwitch","creator_id":"456","creator_name":"someone","video_id":"","game_id":"509658","language":"it","title":"Funny","view_count":7,"created_at":"2020-04-19T20:00:00Z","thumbnail_url":"https://example.com/t.jpg"}],"pagination":{"cursor":"next"}}"#,
+                    ),
+                ])
+                .await;
+                Clips::new(
+                    &server.client(),
+                    "dariomocciatwitch",
+                    "2020-04-18T21:35:06+02:00",
+                    "2020-04-22T19:35:06Z",
+                    None,
+                )
+                .await
+            })
+            .unwrap();
+        assert_eq!(clips.clips.len(), 1);
+        assert_eq!(clips.clips[0].id, "FunnyClip");
+        assert_eq!(clips.cursor.as_deref(), Some("next"));
+
+        // A clip that can't be parsed fails the page instead of being dropped.
+        let clips = runtime.block_on(async {
+            let server = MockServer::start(vec![
+                Route::new("/helix/users", USERS),
+                Route::new("/helix/clips?", r#"{"data":[{"id":7}]}"#),
+            ])
+            .await;
+            Clips::new(
+                &server.client(),
+                "dariomocciatwitch",
+                "2020-04-18T19:35:06Z",
+                "2020-04-22T19:35:06Z",
+                Some("a b"),
+            )
+            .await
+        });
+        assert!(matches!(clips, Err(Error::MalformedPayload(_))));
+    }
+
+    const CLIP_ACCESS_TOKEN: &str = r#"{"data":{"clip":{"id":"1","playbackAccessToken":{"signature":"abcdef","value":"{\"clip_uri\":\"\"}"},"videoQualities":[{"frameRate":30,"quality":"480","sourceURL":"https://clips-media.example.com/480.mp4"},{"frameRate":60,"quality":"1080","sourceURL":"https://clips-media.example.com/1080.mp4"},{"frameRate":60,"quality":"720","sourceURL":"https://clips-media.example.com/720.mp4"}]}}}"#;
+
+    #[test]
+    fn clip_media() {
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let (media, unknown) = runtime.block_on(async {
+            let server = MockServer::start(vec![Route::sequence(
+                "/gql",
+                &[(200, CLIP_ACCESS_TOKEN), (200, r#"{"data":{"clip":null}}"#)],
+            )])
+            .await;
+            let client = server.client();
+            (
+                ClipMedia::new(&client, "FunnyClip").await,
+                ClipMedia::new(&client, "NoClip").await,
+            )
+        });
+        let media = media.unwrap();
+        let names = media
+            .qualities
+            .iter()
+            .map(ClipQuality::name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["1080p60", "720p60", "480p30"]);
+        assert_eq!(media.select("best").unwrap().quality, "1080");
+        assert_eq!(media.select("worst").unwrap().quality, "480");
+        assert_eq!(media.select("360,720").unwrap().quality, "720");
+        assert!(media.select_or_err("360").is_err());
+        assert_eq!(
+            media.url(media.select("480").unwrap()).unwrap(),
+            "https://clips-media.example.com/480.mp4?sig=abcdef&token=%7B%22clip_uri%22%3A%22%22%7D"
+        );
+        match unknown {
+            Err(Error::UnknownClip(slug)) => assert_eq!(slug, "NoClip"),
+            other => panic!("expected UnknownClip, got {:?}", other),
         }
     }
 
+    #[test]
+    fn clip_file_name() {
+        let clip = Clip {
+            id: "FunnyClip".to_string(),
+            broadcaster_name: "DarioMocciaTwitch".to_string(),
+            title: "What?! 1/2".to_string(),
+            created_at: "2020-04-19T20:00:00Z".to_string(),
+            ..Clip::default()
+        };
+        let quality = ClipQuality {
+            quality: "720".to_string(),
+            frame_rate: 59.94,
+            source_url: String::new(),
+        };
+        assert_eq!(
+            clip.file_name(
+                "{broadcaster}/{created_at} {title} [{id}] {quality}.mp4",
+                &quality
+            ),
+            "DarioMocciaTwitch/2020-04-19T20_00_00Z What_! 1_2 [FunnyClip] 720p60.mp4"
+        );
+    }
+
+    /// Body of a page of Helix clips.
+    fn clips_page(clips: &[(&str, &str)], cursor: Option<&str>) -> String {
+        let clip = |(id, created_at): &(&str, &str)| {
+            serde_json::to_value(Clip {
+                id: id.to_string(),
+                created_at: created_at.to_string(),
+                ..Clip::default()
+            })
+            .unwrap()
+        };
+        let clips = clips.iter().map(clip).collect::<Vec<_>>();
+        serde_json::json!({ "data": clips, "pagination": { "cursor": cursor } }).to_string()
+    }
+
+    #[test]
+    fn clips_split_windows() {
+        let a = ("A", "2020-04-18T06:00:00Z");
+        let b = ("B", "2020-04-19T06:00:00Z");
+        let c = ("C", "2020-04-19T18:00:00Z");
+        // Exactly on the boundary between two windows.
+        let d = ("D", "2020-04-19T00:00:00Z");
+        let window = |start: &str, end: &str| {
+            format!(
+                "/helix/clips?broadcaster_id=123&started_at=2020-04-{}Z&ended_at=2020-04-{}Z&first=100",
+                start.replace(':', "%3A"),
+                end.replace(':', "%3A")
+            )
+        };
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let clips = runtime
+            .block_on(async {
+                let server = MockServer::start(vec![
+                    Route::new(
+                        &window("18T00:00:00", "20T00:00:00"),
+                        &clips_page(&[a, b, c], Some("next")),
+                    ),
+                    Route::new(
+                        &window("18T00:00:00", "19T00:00:00"),
+                        &clips_page(&[a, d], None),
+                    ),
+                    Route::new(
+                        &window("19T00:00:00", "20T00:00:00"),
+                        &clips_page(&[d, b, c], None),
+                    ),
+                    Route::new(
+                        &window("19T00:00:00", "19T12:00:00"),
+                        &clips_page(&[d, b], None),
+                    ),
+                    Route::new(
+                        &window("19T12:00:00", "20T00:00:00"),
+                        &clips_page(&[c], None),
+                    ),
+                ])
+                .await;
+                Clips::window(&server.client(), "123", 1587168000, 1587340800, 3).await
+            })
+            .unwrap();
+        assert_eq!(clips.len(), 5);
+        let ids = Clips::merge(clips)
+            .into_iter()
+            .map(|clip| clip.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, ["A", "D", "B", "C"]);
+    }
+
     #[test]
     fn vods() {
         let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
-        let vods = runtime.block_on(Vods::new("dariomocciatwitch", None));
-        match vods {
-            Ok(v) => println!("{:#?}", v),
-            Err(_) => {
-                vods.unwrap();
-            }
-        }
+        let vods = runtime
+            .block_on(async {
+                let server = MockServer::start(vec![Route::new(
+                    "/gql",
+                    r#"[{"data":{"user":{"videos":{"edges":[{"cursor":"c1","node":{"id":"596966295","lengthSeconds":34,"publishedAt":"2020-04-20T18:00:00Z","viewCount":100,"title":"Stream"}}]}}}}]"#,
+                )])
+                .await;
+                Vods::new(&server.client(), "dariomocciatwitch", None).await
+            })
+            .unwrap();
+        assert_eq!(vods.vods.len(), 1);
+        assert_eq!(vods.vods[0].url, "https://www.twitch.tv/videos/596966295");
+        assert_eq!(vods.cursor.as_deref(), Some("c1"));
+    }
+
+    #[test]
+    fn unknown_user_vods() {
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let vods = runtime.block_on(async {
+            let server =
+                MockServer::start(vec![Route::new("/gql", r#"[{"data":{"user":null}}]"#)]).await;
+            Vods::new(&server.client(), "nobody", None).await
+        });
+        assert!(vods.is_err());
     }
 
     #[test]
     fn comments() {
         let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
-        let comments = runtime.block_on(Comments::new("596966295", None));
-        match comments {
-            Ok(c) => println!("{:#?}", c),
-            Err(_) => {
-                comments.unwrap();
-            }
-        }
+        let comments = runtime
+            .block_on(async {
+                let server = MockServer::start(vec![Route::new(
+                    "/v5/videos/596966295/comments",
+                    r##"{"comments":[{"_id":"c1","created_at":"2020-04-20T18:00:05Z","updated_at":"2020-04-20T18:00:05Z","channel_id":"123","content_type":"video","content_id":"596966295","content_offset_seconds":5.2,"commenter":{"display_name":"Viewer","_id":"789","name":"viewer","type":"user","bio":null,"created_at":"2019-01-01T00:00:00Z","updated_at":"2020-01-01T00:00:00Z","logo":"https://example.com/l.png"},"source":"chat","state":"published","message":{"body":"ciao","fragments":[{"text":"ciao"}],"is_action":false,"user_badges":[{"_id":"subscriber","version":"12"}],"user_color":"#FF0000"}}],"_next":"cursor2"}"##,
+                )])
+                .await;
+                Comments::new(&server.client(), "596966295", None).await
+            })
+            .unwrap();
+        assert_eq!(comments.comments.len(), 1);
+        assert_eq!(comments.comments[0].message, "ciao");
+        assert_eq!(comments.comments[0].user.badges[0].id, "subscriber");
+        assert_eq!(comments.cursor.as_deref(), Some("cursor2"));
+    }
+
+    /// Body of a page of v5 comments, each with its ID as message.
+    fn comments_page(comments: &[(&str, f64)], next: Option<&str>) -> String {
+        let comments = comments
+            .iter()
+            .map(|(id, offset)| {
+                serde_json::json!({
+                    "_id": id,
+                    "created_at": "",
+                    "updated_at": "",
+                    "channel_id": "123",
+                    "content_id": "1",
+                    "content_offset_seconds": offset,
+                    "commenter": {"display_name": "V", "name": "v", "bio": null, "created_at": "", "updated_at": "", "logo": ""},
+                    "message": {"body": id},
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::json!({ "comments": comments, "_next": next }).to_string()
+    }
+
+    #[test]
+    fn comments_in_ranges() {
+        let path = "/v5/videos/1/comments?client_id=kimne78kx3ncx6brgo4mv6wki5h1ko&";
+        let page = |query: &str, comments: &[(&str, f64)], next| {
+            Route::new(
+                &format!("{}{}", path, query),
+                &comments_page(comments, next),
+            )
+        };
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let ranges = runtime
+            .block_on(async {
+                let server = MockServer::start(vec![
+                    page(
+                        "content_offset_seconds=0",
+                        &[("a", 1.0), ("b", 9.0)],
+                        Some("c1"),
+                    ),
+                    page("cursor=c1", &[("b", 9.0), ("c", 12.0)], Some("c2")),
+                    page(
+                        "content_offset_seconds=10",
+                        &[("b", 9.0), ("d", 15.0), ("c", 12.0)],
+                        None,
+                    ),
+                    page(
+                        "content_offset_seconds=20",
+                        &[("d", 15.0), ("e", 22.0)],
+                        Some("c3"),
+                    ),
+                    page("cursor=c3", &[("f", 31.0)], None),
+                    Route::new("/v5/videos/1?", r#"{"length":25}"#),
+                ])
+                .await;
+                let client = server.client();
+                let length = Comments::vod_length(&client, "1").await?;
+                Comments::in_ranges(&client, "1", length, 10, 2)
+                    .collect::<Vec<_>>()
+                    .await
+                    .into_iter()
+                    .collect::<Result<Vec<_>>>()
+            })
+            .unwrap();
+        let ids = ranges
+            .iter()
+            .map(|range| range.iter().map(|c| c.id.as_str()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![vec!["a", "b"], vec!["c", "d"], vec!["e", "f"]]);
+    }
+
+    #[test]
+    fn chat_badges() {
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let badges = runtime
+            .block_on(async {
+                let server = MockServer::start(vec![
+                    Route::new(
+                        "/helix/chat/badges/global",
+                        r#"{"data":[{"set_id":"subscriber","versions":[{"id":"0","image_url_1x":"https://example.com/sub0"},{"id":"12","image_url_1x":"https://example.com/sub12"}]},{"set_id":"moderator","versions":[{"id":"1","image_url_1x":"https://example.com/mod"}]}]}"#,
+                    ),
+                    Route::new(
+                        "/helix/chat/badges?broadcaster_id=7",
+                        r#"{"data":[{"set_id":"subscriber","versions":[{"id":"12","image_url_1x":"https://example.com/dario12"}]}]}"#,
+                    ),
+                ])
+                .await;
+                server.client().chat_badges("7").await
+            })
+            .unwrap();
+        let badge = |id: &str, version: &str| CommentUserBadge {
+            id: id.to_string(),
+            version: version.to_string(),
+        };
+        assert_eq!(badges.images.len(), 3);
+        assert_eq!(
+            badges.image(&badge("subscriber", "12")),
+            Some("https://example.com/dario12")
+        );
+        assert_eq!(
+            badges.image(&badge("moderator", "1")),
+            Some("https://example.com/mod")
+        );
+        assert_eq!(badges.image(&badge("vip", "1")), None);
+    }
+
+    #[test]
+    fn message_fragments() {
+        let text = |text: &str| Fragment::Text {
+            text: text.to_string(),
+        };
+        let message = serde_json::json!({
+            "body": "Kappa @Bob_1, see https://twitch.tv cheer100 Kappa",
+            "bits_spent": 100,
+            "fragments": [
+                {"text": "Kappa", "emoticon": {"emoticon_id": "25"}},
+                {"text": " @Bob_1, see https://twitch.tv cheer100 "},
+                {"text": "Kappa", "emoticon": {"emoticon_id": "25"}},
+            ],
+        });
+        let kappa = |begin| Fragment::Emote {
+            text: "Kappa".to_string(),
+            id: "25".to_string(),
+            source: EmoteSource::Twitch,
+            begin,
+            end: begin + 4,
+        };
+        assert_eq!(
+            Fragment::from_message(&message),
+            vec![
+                kappa(0),
+                text(" "),
+                Fragment::Mention {
+                    text: "@Bob_1".to_string(),
+                    login: "bob_1".to_string(),
+                },
+                text(", see "),
+                Fragment::Link {
+                    text: "https://twitch.tv".to_string(),
+                    url: "https://twitch.tv".to_string(),
+                },
+                text(" "),
+                Fragment::Cheer {
+                    text: "cheer100".to_string(),
+                    prefix: "cheer".to_string(),
+                    bits: 100,
+                },
+                text(" "),
+                kappa(45),
+            ]
+        );
+        assert_eq!(
+            Fragment::from_message(&serde_json::json!({"body": "cheer100 hi"})),
+            vec![text("cheer100 hi")]
+        );
+        assert_eq!(
+            serde_json::to_value(kappa(0)).unwrap(),
+            serde_json::json!({"type": "emote", "text": "Kappa", "id": "25", "source": "twitch", "begin": 0, "end": 4})
+        );
     }
 
     #[test]
     fn m3u8_gen() {
         let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
-        let m3u8 = runtime.block_on(Vod::m3u8_gen("596966295", Some(20.1), Some(140.3)));
-        match m3u8 {
-            Ok(m) => println!("{:#?}", m),
-            Err(_) => {
-                m3u8.unwrap();
-            }
-        }
+        let (m3u8, base) = runtime.block_on(async {
+            let server = MockServer::start(vec![
+                Route::new("/api/vods/596966295/access_token", ACCESS_TOKEN),
+                Route::new("/vod/596966295.m3u8", MASTER_PLAYLIST),
+                Route::new("/vod/chunked/index-dvr.m3u8", MEDIA_PLAYLIST),
+            ])
+            .await;
+            (
+                Vod::m3u8_gen(
+                    &server.client(),
+                    "596966295",
+                    "best",
+                    Some(15.0),
+                    Some(30.0),
+                )
+                .await,
+                server.url(),
+            )
+        });
+        assert_eq!(
+            m3u8.unwrap(),
+            format!(
+                "#EXTM3U
+#EXT-X-VERSION:3
+#EXT-X-TARGETDURATION:10
+#EXT-X-MEDIA-SEQUENCE:1
+#EXT-X-START:TIME-OFFSET=5.000,PRECISE=YES
+#EXTINF:10.000,
+{base}/vod/chunked/1-muted.ts
+#EXTINF:9.500,
+{base}/vod/chunked/2.ts
+#EXTINF:4.500,
+{base}/vod/chunked/3.ts
+#EXT-X-ENDLIST
+",
+                base = base
+            )
+        );
     }
 }
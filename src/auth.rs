@@ -0,0 +1,404 @@
+use crate::client::{HelixCredentials, TwitchClient};
+use crate::error::{check_status, Error, Result};
+use crate::files::sidecar;
+use crate::time::now;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Tokens expiring in less than this many seconds are refreshed before use.
+const EXPIRY_MARGIN: u64 = 60;
+
+/// OAuth token stored on disk by `twitch-cli auth`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Token {
+    pub client_id: String,
+    /// Only stored for tokens obtained with the client credentials flow, which
+    /// can't be refreshed and have to be requested again instead.
+    pub client_secret: Option<String>,
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    /// Unix timestamp (in seconds) of when `access_token` expires.
+    pub expires_at: Option<u64>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_in: Option<u64>,
+    #[serde(default)]
+    scope: Vec<String>,
+}
+
+/// Response of the first step of the device code flow.
+#[derive(Deserialize, Debug, Clone)]
+pub struct DeviceCode {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    pub expires_in: u64,
+    pub interval: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Validation {
+    client_id: String,
+    expires_in: Option<u64>,
+}
+
+impl Token {
+    fn from_response(resp: TokenResponse, client_id: &str, client_secret: Option<&str>) -> Token {
+        Token {
+            client_id: client_id.to_string(),
+            client_secret: client_secret.map(|x| x.to_string()),
+            access_token: resp.access_token,
+            refresh_token: resp.refresh_token,
+            expires_at: resp.expires_in.map(|secs| now() + secs),
+            scopes: resp.scope,
+        }
+    }
+
+    /// Path of the token file used when none is given explicitly.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("twitch-cli").join("token.json"))
+    }
+
+    /// Loads the token stored at `path`, returning `None` if there is none.
+    pub fn load(path: &Path) -> Result<Option<Token>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(path)?;
+        serde_json::from_str(&content).map(Some).map_err(|err| {
+            Error::Config(format!("invalid token file `{}`: {}", path.display(), err))
+        })
+    }
+
+    /// Stores the token at `path`, readable only by the current user. The
+    /// token is written to a temporary file created with those permissions
+    /// and then renamed, so that it's never readable by others.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = sidecar(path, ".tmp");
+        // A leftover temporary file may have other permissions.
+        match std::fs::remove_file(&tmp) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => (),
+        }
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&tmp)?;
+        std::io::Write::write_all(&mut file, serde_json::to_string_pretty(self)?.as_bytes())?;
+        drop(file);
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .map(|expires_at| expires_at <= now() + EXPIRY_MARGIN)
+            .unwrap_or(false)
+    }
+
+    pub fn helix_credentials(&self) -> HelixCredentials {
+        HelixCredentials {
+            client_id: self.client_id.clone(),
+            access_token: self.access_token.clone(),
+        }
+    }
+}
+
+async fn request_token(
+    client: &TwitchClient,
+    params: &[(&str, &str)],
+) -> Result<reqwest::Response> {
+    Ok(client
+        .post(&format!("{}/token", client.endpoints().oauth))
+        .form(params)
+        .send()
+        .await?)
+}
+
+async fn token_error(resp: reqwest::Response) -> Error {
+    let status = resp.status();
+    let message = resp
+        .json::<serde_json::Value>()
+        .await
+        .ok()
+        .and_then(|body| body["message"].as_str().map(|x| x.to_string()))
+        .unwrap_or_else(|| status.to_string());
+    Error::Auth(format!("Twitch refused the token request: {}", message))
+}
+
+/// Gets an app access token with the client credentials flow.
+pub async fn client_credentials(
+    client: &TwitchClient,
+    client_id: &str,
+    client_secret: &str,
+) -> Result<Token> {
+    let resp = request_token(
+        client,
+        &[
+            ("client_id", client_id),
+            ("client_secret", client_secret),
+            ("grant_type", "client_credentials"),
+        ],
+    )
+    .await?;
+    if !resp.status().is_success() {
+        return Err(token_error(resp).await);
+    }
+    Ok(Token::from_response(
+        resp.json().await?,
+        client_id,
+        Some(client_secret),
+    ))
+}
+
+/// Starts the device code flow. The user has to visit
+/// `DeviceCode::verification_uri` and enter `DeviceCode::user_code`, while
+/// `device_token` waits for them to do so.
+pub async fn device_code(
+    client: &TwitchClient,
+    client_id: &str,
+    scopes: &[&str],
+) -> Result<DeviceCode> {
+    let resp = client
+        .post(&format!("{}/device", client.endpoints().oauth))
+        .form(&[("client_id", client_id), ("scopes", &scopes.join(" "))])
+        .send()
+        .await?;
+    if !resp.status().is_success() {
+        return Err(token_error(resp).await);
+    }
+    Ok(resp.json().await?)
+}
+
+/// Polls Twitch until the user authorizes `code`, or until it expires.
+pub async fn device_token(
+    client: &TwitchClient,
+    client_id: &str,
+    scopes: &[&str],
+    code: &DeviceCode,
+) -> Result<Token> {
+    let scopes = scopes.join(" ");
+    let deadline = now() + code.expires_in;
+    let mut interval = code.interval;
+
+    loop {
+        let resp = request_token(
+            client,
+            &[
+                ("client_id", client_id),
+                ("scopes", &scopes),
+                ("device_code", &code.device_code),
+                ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+            ],
+        )
+        .await?;
+        if resp.status().is_success() {
+            return Ok(Token::from_response(resp.json().await?, client_id, None));
+        }
+
+        let body = resp.json::<serde_json::Value>().await.unwrap_or_default();
+        match body["message"].as_str() {
+            Some("authorization_pending") => {}
+            Some("slow_down") => interval += 5,
+            Some(message) => {
+                return Err(Error::Auth(format!(
+                    "device authorization failed: {}",
+                    message
+                )))
+            }
+            None => return Err(Error::Auth("device authorization failed".into())),
+        }
+        if now() >= deadline {
+            return Err(Error::Auth(
+                "the device code expired before it was authorized".into(),
+            ));
+        }
+        tokio::time::delay_for(Duration::from_secs(interval)).await;
+    }
+}
+
+/// Gets a new access token for `token`, either with its refresh token or, for
+/// app access tokens, by repeating the client credentials flow.
+pub async fn refresh(client: &TwitchClient, token: &Token) -> Result<Token> {
+    if let Some(refresh_token) = &token.refresh_token {
+        let mut params = vec![
+            ("client_id", token.client_id.as_str()),
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token.as_str()),
+        ];
+        if let Some(secret) = &token.client_secret {
+            params.push(("client_secret", secret.as_str()));
+        }
+        let resp = request_token(client, &params).await?;
+        if !resp.status().is_success() {
+            return Err(token_error(resp).await);
+        }
+        let mut refreshed = Token::from_response(
+            resp.json().await?,
+            &token.client_id,
+            token.client_secret.as_deref(),
+        );
+        if refreshed.scopes.is_empty() {
+            refreshed.scopes = token.scopes.clone();
+        }
+        return Ok(refreshed);
+    }
+
+    match &token.client_secret {
+        Some(secret) => client_credentials(client, &token.client_id, secret).await,
+        None => Err(Error::Auth(
+            "the stored token can't be refreshed, run `twitch-cli auth` again".into(),
+        )),
+    }
+}
+
+/// Checks `token` against Twitch. Returns `false` if Twitch doesn't accept
+/// it anymore.
+pub async fn validate(client: &TwitchClient, token: &Token) -> Result<bool> {
+    let resp = client
+        .get(&format!("{}/validate", client.endpoints().oauth))
+        .header(
+            reqwest::header::AUTHORIZATION,
+            format!("OAuth {}", token.access_token),
+        )
+        .send()
+        .await?;
+    if resp.status() == reqwest::StatusCode::UNAUTHORIZED {
+        return Ok(false);
+    }
+    let validation: Validation = check_status(resp)?.json().await?;
+    Ok(validation.client_id == token.client_id && validation.expires_in != Some(0))
+}
+
+/// Loads the token stored at `path`, refreshing it if it expired or if Twitch
+/// doesn't accept it anymore. The refreshed token is stored back at `path`.
+pub async fn load_token(client: &TwitchClient, path: &Path) -> Result<Option<Token>> {
+    let token = match Token::load(path)? {
+        Some(token) => token,
+        None => return Ok(None),
+    };
+
+    if !token.is_expired() && validate(client, &token).await? {
+        return Ok(Some(token));
+    }
+    let token = refresh(client, &token).await?;
+    token.save(path)?;
+    Ok(Some(token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockServer, Route};
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn client_credentials_flow() {
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let token = runtime
+            .block_on(async {
+                let server = MockServer::start(vec![Route::new(
+                    "/oauth2/token",
+                    r#"{"access_token":"apptoken","expires_in":5000,"token_type":"bearer"}"#,
+                )])
+                .await;
+                client_credentials(&server.client(), "myclient", "secret").await
+            })
+            .unwrap();
+        assert_eq!(token.access_token, "apptoken");
+        assert_eq!(token.client_secret.as_deref(), Some("secret"));
+        assert!(!token.is_expired());
+    }
+
+    #[test]
+    fn device_code_flow() {
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let token = runtime
+            .block_on(async {
+                let server = MockServer::start(vec![
+                    Route::new(
+                        "/oauth2/device",
+                        r#"{"device_code":"dev","user_code":"ABCDEFGH","verification_uri":"https://www.twitch.tv/activate","expires_in":1800,"interval":0}"#,
+                    ),
+                    Route::sequence(
+                        "/oauth2/token",
+                        &[
+                            (400, r#"{"status":400,"message":"authorization_pending"}"#),
+                            (
+                                200,
+                                r#"{"access_token":"usertoken","refresh_token":"refresh","expires_in":14000,"scope":["user:read:email"],"token_type":"bearer"}"#,
+                            ),
+                        ],
+                    ),
+                ])
+                .await;
+                let client = server.client();
+                let code = device_code(&client, "myclient", &["user:read:email"]).await?;
+                assert_eq!(code.user_code, "ABCDEFGH");
+                device_token(&client, "myclient", &["user:read:email"], &code).await
+            })
+            .unwrap();
+        assert_eq!(token.access_token, "usertoken");
+        assert_eq!(token.refresh_token.as_deref(), Some("refresh"));
+        assert_eq!(token.scopes, vec!["user:read:email"]);
+    }
+
+    #[test]
+    fn refreshes_invalid_token() {
+        let dir = std::env::temp_dir().join(format!("twitch-cli-test-{}-auth", std::process::id()));
+        let path = dir.join("token.json");
+        Token {
+            client_id: "myclient".to_string(),
+            client_secret: None,
+            access_token: "revoked".to_string(),
+            refresh_token: Some("refresh".to_string()),
+            expires_at: None,
+            scopes: vec![],
+        }
+        .save(&path)
+        .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let token = runtime
+            .block_on(async {
+                let server = MockServer::start(vec![
+                    Route::with_status(
+                        "/oauth2/validate",
+                        401,
+                        r#"{"status":401,"message":"invalid access token"}"#,
+                    ),
+                    Route::new(
+                        "/oauth2/token",
+                        r#"{"access_token":"fresh","refresh_token":"refresh2","expires_in":14000,"token_type":"bearer"}"#,
+                    ),
+                ])
+                .await;
+                load_token(&server.client(), &path).await
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(token.access_token, "fresh");
+        assert_eq!(Token::load(&path).unwrap(), Some(token));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
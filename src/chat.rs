@@ -0,0 +1,173 @@
+//! Plain text logs of the chat of VODs.
+
+use crate::api::Comment;
+use crate::error::Result;
+use crate::time;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Renders a comment as `[HH:MM:SS] [badges] display_name: message`. The
+/// time is the offset in the VOD, or with `wallclock` the UTC date and time
+/// the comment was posted at.
+pub fn log_line(comment: &Comment, wallclock: bool) -> String {
+    let timestamp = match time::parse_rfc3339(&comment.created_at) {
+        Some(created_at) if wallclock => time::format_utc(created_at),
+        _ => {
+            let offset = comment.offset().as_secs();
+            format!(
+                "{:02}:{:02}:{:02}",
+                offset / 3600,
+                offset / 60 % 60,
+                offset % 60
+            )
+        }
+    };
+    let badges = comment
+        .user
+        .badges
+        .iter()
+        .map(|badge| format!("{}/{}", badge.id, badge.version))
+        .collect::<Vec<_>>();
+    let badges = if badges.is_empty() {
+        String::new()
+    } else {
+        format!("[{}] ", badges.join(","))
+    };
+    format!(
+        "[{}] {}{}: {}",
+        timestamp,
+        badges,
+        comment.user.display_name,
+        comment.message.replace(['\n', '\r'], " ")
+    )
+}
+
+/// Writes chat logs to stdout, to a file, or to one file per hour of VOD.
+pub struct LogWriter {
+    output: Option<PathBuf>,
+    split: bool,
+    wallclock: bool,
+    /// Hour of the file being written, and the file.
+    current: Option<(u64, BufWriter<File>)>,
+}
+
+impl LogWriter {
+    /// With `split`, `output` is required and the comments of the n-th hour of
+    /// the VOD go to `output` with `.n` inserted before its extension (e.g.
+    /// `chat.02.log`).
+    pub fn new(output: Option<&Path>, split: bool, wallclock: bool) -> LogWriter {
+        LogWriter {
+            output: output.map(Path::to_path_buf),
+            split,
+            wallclock,
+            current: None,
+        }
+    }
+
+    /// Path of the file of an hour of the VOD.
+    pub fn hour_path(output: &Path, hour: u64) -> PathBuf {
+        let mut name = output.file_stem().unwrap_or_default().to_os_string();
+        name.push(format!(".{:02}", hour));
+        if let Some(extension) = output.extension() {
+            name.push(".");
+            name.push(extension);
+        }
+        output.with_file_name(name)
+    }
+
+    /// Comments are expected in the order of the VOD.
+    pub fn write(&mut self, comment: &Comment) -> Result<()> {
+        let line = log_line(comment, self.wallclock);
+        let output = match &self.output {
+            Some(output) => output,
+            None => {
+                println!("{}", line);
+                return Ok(());
+            }
+        };
+        let hour = if self.split {
+            comment.offset().as_secs() / 3600
+        } else {
+            0
+        };
+        match &mut self.current {
+            Some((current, file)) if *current == hour => writeln!(file, "{}", line)?,
+            current => {
+                let path = if self.split {
+                    Self::hour_path(output, hour)
+                } else {
+                    output.clone()
+                };
+                if let Some((_, file)) = current {
+                    file.flush()?;
+                }
+                let mut file = BufWriter::new(File::create(path)?);
+                writeln!(file, "{}", line)?;
+                *current = Some((hour, file));
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes the file being written.
+    pub fn finish(&mut self) -> Result<()> {
+        if let Some((_, file)) = &mut self.current {
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::test_support::comment;
+    use crate::api::CommentUserBadge;
+
+    #[test]
+    fn log_lines() {
+        let mut moderator = comment().offset(3723.9).message("hi\nall").build();
+        moderator.created_at = "2020-05-01T18:00:00.123Z".to_string();
+        moderator.user.badges = [("moderator", "1"), ("subscriber", "12")]
+            .iter()
+            .map(|(id, version)| CommentUserBadge {
+                id: id.to_string(),
+                version: version.to_string(),
+            })
+            .collect();
+        assert_eq!(
+            log_line(&moderator, false),
+            "[01:02:03] [moderator/1,subscriber/12] Alice: hi all"
+        );
+        assert_eq!(
+            log_line(&moderator, true),
+            "[2020-05-01 18:00:00] [moderator/1,subscriber/12] Alice: hi all"
+        );
+        assert_eq!(
+            log_line(&comment().offset(5.0).message("hi").build(), false),
+            "[00:00:05] Alice: hi"
+        );
+    }
+
+    #[test]
+    fn split_by_hour() {
+        let dir = std::env::temp_dir().join(format!("twitch-cli-test-{}-chat", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("chat.log");
+        assert_eq!(LogWriter::hour_path(&output, 2), dir.join("chat.02.log"));
+
+        let mut writer = LogWriter::new(Some(&output), true, false);
+        for offset in &[10.0, 3599.0, 3600.0, 7300.0] {
+            writer
+                .write(&comment().offset(*offset).message("hi").build())
+                .unwrap();
+        }
+        writer.finish().unwrap();
+        let read = |hour| std::fs::read_to_string(LogWriter::hour_path(&output, hour)).unwrap();
+        assert_eq!(read(0), "[00:00:10] Alice: hi\n[00:59:59] Alice: hi\n");
+        assert_eq!(read(1), "[01:00:00] Alice: hi\n");
+        assert_eq!(read(2), "[02:01:40] Alice: hi\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
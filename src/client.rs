@@ -0,0 +1,236 @@
+use crate::api::{
+    ChatBadges, Chatters, Clip, ClipMedia, Clips, Comment, Comments, LiveStatus, Segment, Stream,
+    UserData, UserQuery, Vod, Vods,
+};
+use crate::config::Endpoints;
+use crate::error::{Error, Result};
+use crate::hls::{Cut, MasterPlaylist, MediaPlaylist, Variant};
+use reqwest::header::{HeaderMap, HeaderValue};
+use std::collections::BTreeMap;
+
+const CLIENT_ID: &str = "kimne78kx3ncx6brgo4mv6wki5h1ko";
+const USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/81.0.4044.113 Safari/537.36";
+
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    /// Client ID sent to the private APIs used by the Twitch website.
+    pub client_id: String,
+    /// Credentials for the Helix API, obtained with `twitch-cli auth`.
+    pub helix: Option<HelixCredentials>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HelixCredentials {
+    pub client_id: String,
+    pub access_token: String,
+}
+
+impl Default for Credentials {
+    fn default() -> Credentials {
+        Credentials {
+            client_id: CLIENT_ID.to_string(),
+            helix: None,
+        }
+    }
+}
+
+/// Handle to the Twitch APIs. It owns a single connection pool that is reused
+/// by every request, so it should be created once and shared.
+#[derive(Debug, Clone)]
+pub struct TwitchClient {
+    http: reqwest::Client,
+    endpoints: Endpoints,
+    credentials: Credentials,
+}
+
+impl TwitchClient {
+    pub fn new(endpoints: Endpoints, credentials: Credentials) -> Result<TwitchClient> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Client-Id",
+            HeaderValue::from_str(&credentials.client_id)
+                .map_err(|_| Error::Config("invalid client ID".into()))?,
+        );
+        headers.insert(
+            reqwest::header::USER_AGENT,
+            HeaderValue::from_static(USER_AGENT),
+        );
+        let http = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()?;
+
+        Ok(TwitchClient {
+            http,
+            endpoints,
+            credentials,
+        })
+    }
+
+    pub fn endpoints(&self) -> &Endpoints {
+        &self.endpoints
+    }
+
+    pub fn credentials(&self) -> &Credentials {
+        &self.credentials
+    }
+
+    pub fn set_helix_credentials(&mut self, helix: HelixCredentials) {
+        self.credentials.helix = Some(helix);
+    }
+
+    /// GET request with the default headers only.
+    pub fn get(&self, url: &str) -> reqwest::RequestBuilder {
+        self.http.get(url)
+    }
+
+    pub fn post(&self, url: &str) -> reqwest::RequestBuilder {
+        self.http.post(url)
+    }
+
+    /// GET request to `path` on the Helix API, authenticated with the Helix
+    /// credentials if there are any.
+    pub fn helix(&self, path: &str) -> reqwest::RequestBuilder {
+        let req = self
+            .http
+            .get(&format!("{}/helix/{}", self.endpoints.api, path));
+        match &self.credentials.helix {
+            Some(helix) => req
+                .header("Client-Id", &helix.client_id)
+                .bearer_auth(&helix.access_token),
+            None => req,
+        }
+    }
+
+    pub async fn user(&self, username: &str) -> Result<UserData> {
+        UserData::new(self, username).await
+    }
+
+    pub async fn users(&self, queries: &[UserQuery]) -> Result<(Vec<UserData>, Vec<UserQuery>)> {
+        UserData::many(self, queries).await
+    }
+
+    pub async fn is_live(&self, channel_name: &str) -> Result<bool> {
+        UserData::is_live(self, channel_name).await
+    }
+
+    pub async fn stream(&self, channel_name: &str) -> Result<Option<Stream>> {
+        Stream::new(self, channel_name).await
+    }
+
+    pub async fn streams(&self, channel_names: &[&str]) -> Result<Vec<Stream>> {
+        Stream::many(self, channel_names).await
+    }
+
+    pub async fn are_live(&self, channel_names: &[&str]) -> BTreeMap<String, LiveStatus> {
+        UserData::are_live(self, channel_names).await
+    }
+
+    pub async fn fetch_live_link(&self, channel_name: &str) -> Result<String> {
+        UserData::fetch_live_link(self, channel_name).await
+    }
+
+    pub async fn live_master_playlist(&self, channel_name: &str) -> Result<MasterPlaylist> {
+        UserData::live_master_playlist(self, channel_name).await
+    }
+
+    pub async fn live_variant(&self, channel_name: &str, quality: &str) -> Result<Variant> {
+        UserData::live_variant(self, channel_name, quality).await
+    }
+
+    pub async fn chatters(&self, username: &str) -> Result<Chatters> {
+        Chatters::new(self, username).await
+    }
+
+    pub async fn chat_badges(&self, broadcaster_id: &str) -> Result<ChatBadges> {
+        ChatBadges::new(self, broadcaster_id).await
+    }
+
+    pub async fn clip(&self, slug: &str) -> Result<Clip> {
+        Clip::new(self, slug).await
+    }
+
+    pub async fn clip_media(&self, slug: &str) -> Result<ClipMedia> {
+        ClipMedia::new(self, slug).await
+    }
+
+    pub async fn clips(
+        &self,
+        username: &str,
+        started_at: &str,
+        ended_at: &str,
+        cursor: Option<&str>,
+    ) -> Result<Clips> {
+        Clips::new(self, username, started_at, ended_at, cursor).await
+    }
+
+    pub async fn all_clips(
+        &self,
+        username: &str,
+        started_at: i64,
+        ended_at: i64,
+    ) -> Result<Vec<Clip>> {
+        Clips::all(self, username, started_at, ended_at).await
+    }
+
+    pub async fn vods(&self, username: &str, cursor: Option<&str>) -> Result<Vods> {
+        Vods::new(self, username, cursor).await
+    }
+
+    pub async fn comments(&self, vodid: &str, cursor: Option<&str>) -> Result<Comments> {
+        Comments::new(self, vodid, cursor).await
+    }
+
+    pub async fn all_comments<'a>(
+        &'a self,
+        vodid: &'a str,
+        parallelism: usize,
+    ) -> Result<impl futures::Stream<Item = Result<Vec<Comment>>> + 'a> {
+        Comments::all(self, vodid, parallelism).await
+    }
+
+    pub async fn fetch_direct_link(&self, vodid: &str) -> Result<String> {
+        Vod::fetch_direct_link(self, vodid).await
+    }
+
+    pub async fn master_playlist(&self, vodid: &str) -> Result<MasterPlaylist> {
+        Vod::master_playlist(self, vodid).await
+    }
+
+    pub async fn variant(&self, vodid: &str, quality: &str) -> Result<Variant> {
+        Vod::variant(self, vodid, quality).await
+    }
+
+    pub async fn media_playlist(&self, vodid: &str, quality: &str) -> Result<MediaPlaylist> {
+        Vod::media_playlist(self, vodid, quality).await
+    }
+
+    pub async fn trimmed_playlist(
+        &self,
+        vodid: &str,
+        quality: &str,
+        start: Option<f64>,
+        end: Option<f64>,
+    ) -> Result<(MediaPlaylist, Cut)> {
+        Vod::trimmed_playlist(self, vodid, quality, start, end).await
+    }
+
+    pub async fn segments(
+        &self,
+        vodid: &str,
+        quality: &str,
+        start: Option<f64>,
+        end: Option<f64>,
+    ) -> Result<Vec<Segment>> {
+        Vod::segments(self, vodid, quality, start, end).await
+    }
+
+    pub async fn m3u8_gen(
+        &self,
+        vodid: &str,
+        quality: &str,
+        start: Option<f64>,
+        end: Option<f64>,
+    ) -> Result<String> {
+        Vod::m3u8_gen(self, vodid, quality, start, end).await
+    }
+}
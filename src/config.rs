@@ -0,0 +1,252 @@
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Base URLs of every host the CLI talks to. Each one can be overridden to
+/// point the CLI at a local stand-in of the Twitch APIs.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Endpoints {
+    pub api: String,
+    pub gql: String,
+    pub tmi: String,
+    pub usher: String,
+    pub oauth: String,
+    /// BetterTTV, FrankerFaceZ and 7TV, which host third-party emotes.
+    pub bttv: String,
+    pub ffz: String,
+    pub seventv: String,
+    /// Hosts of the emote images of Twitch and of each third-party service.
+    #[serde(rename = "twitch-cdn")]
+    pub twitch_cdn: String,
+    #[serde(rename = "bttv-cdn")]
+    pub bttv_cdn: String,
+    #[serde(rename = "ffz-cdn")]
+    pub ffz_cdn: String,
+    #[serde(rename = "seventv-cdn")]
+    pub seventv_cdn: String,
+}
+
+impl Default for Endpoints {
+    fn default() -> Endpoints {
+        Endpoints {
+            api: "https://api.twitch.tv".to_string(),
+            gql: "https://gql.twitch.tv".to_string(),
+            tmi: "https://tmi.twitch.tv".to_string(),
+            usher: "https://usher.ttvnw.net".to_string(),
+            oauth: "https://id.twitch.tv/oauth2".to_string(),
+            bttv: "https://api.betterttv.net/3".to_string(),
+            ffz: "https://api.frankerfacez.com/v1".to_string(),
+            seventv: "https://7tv.io/v3".to_string(),
+            twitch_cdn: "https://static-cdn.jtvnw.net".to_string(),
+            bttv_cdn: "https://cdn.betterttv.net".to_string(),
+            ffz_cdn: "https://cdn.frankerfacez.com".to_string(),
+            seventv_cdn: "https://cdn.7tv.app".to_string(),
+        }
+    }
+}
+
+impl Endpoints {
+    /// Names of the endpoints, as in the config file.
+    pub const NAMES: &'static [&'static str] = &[
+        "api",
+        "gql",
+        "tmi",
+        "usher",
+        "oauth",
+        "bttv",
+        "ffz",
+        "seventv",
+        "twitch-cdn",
+        "bttv-cdn",
+        "ffz-cdn",
+        "seventv-cdn",
+    ];
+
+    /// Overrides every endpoint for which `overrides` returns `Some`, given
+    /// its name, stripping trailing slashes so that paths can be appended
+    /// with `format!("{}/...")`.
+    pub fn with_overrides<'a, F>(mut self, overrides: F) -> Endpoints
+    where
+        F: Fn(&str) -> Option<&'a str>,
+    {
+        let endpoints = vec![
+            &mut self.api,
+            &mut self.gql,
+            &mut self.tmi,
+            &mut self.usher,
+            &mut self.oauth,
+            &mut self.bttv,
+            &mut self.ffz,
+            &mut self.seventv,
+            &mut self.twitch_cdn,
+            &mut self.bttv_cdn,
+            &mut self.ffz_cdn,
+            &mut self.seventv_cdn,
+        ];
+        for (endpoint, name) in endpoints.into_iter().zip(Self::NAMES) {
+            if let Some(value) = overrides(name) {
+                *endpoint = value.to_string();
+            }
+            let trimmed_len = endpoint.trim_end_matches('/').len();
+            endpoint.truncate(trimmed_len);
+        }
+        self
+    }
+}
+
+/// A channel recorded by `watch`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WatchedChannel {
+    pub name: String,
+    /// Directory the recordings are written to.
+    #[serde(default = "WatchedChannel::default_output_dir")]
+    pub output_dir: PathBuf,
+    /// Name of the recordings, where `{channel}`, `{id}` (the ID of the
+    /// broadcast) and `{timestamp}` (Unix time of the start of the recording)
+    /// are replaced.
+    #[serde(default = "WatchedChannel::default_template")]
+    pub template: String,
+    /// Overrides `WatchConfig::quality`.
+    pub quality: Option<String>,
+}
+
+impl WatchedChannel {
+    fn default_output_dir() -> PathBuf {
+        PathBuf::from(".")
+    }
+
+    fn default_template() -> String {
+        "{channel}-{id}.ts".to_string()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct WatchConfig {
+    /// Seconds between two checks of which channels are live.
+    pub interval: u64,
+    pub quality: String,
+    /// Where `watch` keeps track of the broadcasts it recorded, defaults to
+    /// `WatchState::default_path`.
+    pub state_file: Option<PathBuf>,
+    pub channels: Vec<WatchedChannel>,
+}
+
+impl Default for WatchConfig {
+    fn default() -> WatchConfig {
+        WatchConfig {
+            interval: 60,
+            quality: "best".to_string(),
+            state_file: None,
+            channels: vec![],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct Config {
+    pub endpoints: Endpoints,
+    pub watch: WatchConfig,
+}
+
+impl Config {
+    /// Path of the config file used when none is given explicitly.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("twitch-cli").join("config.toml"))
+    }
+
+    /// Loads the config file at `path`, or at `Config::default_path` if `path`
+    /// is `None`. A missing default config file is not an error.
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let (path, explicit) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match Self::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+
+        if !explicit && !path.exists() {
+            return Ok(Config::default());
+        }
+        let content = std::fs::read_to_string(&path).map_err(|err| {
+            Error::Config(format!(
+                "can't read config file `{}`: {}",
+                path.display(),
+                err
+            ))
+        })?;
+        toml::from_str(&content).map_err(|err| {
+            Error::Config(format!("invalid config file `{}`: {}", path.display(), err))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_config_file() {
+        let config: Config = toml::from_str(
+            r#"
+[endpoints]
+api = "http://127.0.0.1:8080/"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.endpoints.api, "http://127.0.0.1:8080/");
+        assert_eq!(config.endpoints.gql, Endpoints::default().gql);
+        assert_eq!(config.watch, WatchConfig::default());
+    }
+
+    #[test]
+    fn watch_config() {
+        let config: Config = toml::from_str(
+            r#"
+[watch]
+interval = 30
+
+[[watch.channels]]
+name = "dariomocciatwitch"
+
+[[watch.channels]]
+name = "sabaku_no_maiku"
+output_dir = "/recordings"
+template = "{timestamp}.ts"
+quality = "720p60,best"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.watch.interval, 30);
+        assert_eq!(config.watch.quality, "best");
+        let channels = &config.watch.channels;
+        assert_eq!(channels[0].output_dir, PathBuf::from("."));
+        assert_eq!(channels[0].template, "{channel}-{id}.ts");
+        assert_eq!(channels[1].output_dir, PathBuf::from("/recordings"));
+        assert_eq!(channels[1].quality.as_deref(), Some("720p60,best"));
+    }
+
+    #[test]
+    fn overrides() {
+        let endpoints = Endpoints::default().with_overrides(|name| match name {
+            "api" => Some("http://localhost:1234/"),
+            "tmi" => Some("http://localhost:4321"),
+            "oauth" => Some("http://localhost:4321/oauth2/"),
+            "seventv" => Some("http://localhost:4321/7tv/"),
+            "ffz-cdn" => Some("http://localhost:4321/ffz-cdn"),
+            _ => None,
+        });
+        assert_eq!(endpoints.api, "http://localhost:1234");
+        assert_eq!(endpoints.gql, "https://gql.twitch.tv");
+        assert_eq!(endpoints.tmi, "http://localhost:4321");
+        assert_eq!(endpoints.usher, "https://usher.ttvnw.net");
+        assert_eq!(endpoints.oauth, "http://localhost:4321/oauth2");
+        assert_eq!(endpoints.seventv, "http://localhost:4321/7tv");
+        assert_eq!(endpoints.bttv, "https://api.betterttv.net/3");
+        assert_eq!(endpoints.ffz_cdn, "http://localhost:4321/ffz-cdn");
+        assert_eq!(endpoints.bttv_cdn, "https://cdn.betterttv.net");
+    }
+}
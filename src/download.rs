@@ -0,0 +1,589 @@
+use crate::api::Segment;
+use crate::client::TwitchClient;
+use crate::error::{check_status, Error, Result};
+use crate::files::sidecar;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// Maximum number of segments fetched at the same time.
+    pub workers: usize,
+    /// How many times a segment is requested again after a failure.
+    pub retries: u32,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> DownloadOptions {
+        DownloadOptions {
+            workers: 8,
+            retries: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub bytes: u64,
+    pub segments_done: usize,
+    pub segments_total: usize,
+    /// Segments that were already downloaded by a previous run.
+    pub resumed: usize,
+    started: Instant,
+}
+
+impl Progress {
+    fn new(segments_total: usize) -> Progress {
+        Progress {
+            bytes: 0,
+            segments_done: 0,
+            segments_total,
+            resumed: 0,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Estimated time left, extrapolated from the segments downloaded so far.
+    pub fn eta(&self) -> Option<Duration> {
+        let fetched = self.segments_done - self.resumed;
+        if fetched == 0 {
+            return None;
+        }
+        let remaining = (self.segments_total - self.segments_done) as f64;
+        Some(self.elapsed().mul_f64(remaining / fetched as f64))
+    }
+}
+
+/// URI of the muted copy of a segment, which Twitch serves in place of the
+/// original when the audio of the segment has been muted.
+fn muted_uri(uri: &str) -> Option<String> {
+    if uri.ends_with("-muted.ts") {
+        None
+    } else if uri.ends_with("-unmuted.ts") {
+        Some(uri.replace("-unmuted.ts", "-muted.ts"))
+    } else if uri.ends_with(".ts") {
+        Some(format!("{}-muted.ts", uri.trim_end_matches(".ts")))
+    } else {
+        None
+    }
+}
+
+pub(crate) fn is_retryable(err: &Error) -> bool {
+    matches!(
+        err,
+        Error::Network(_) | Error::RateLimited(_) | Error::Server(_, _)
+    )
+}
+
+async fn fetch(client: &TwitchClient, uri: &str) -> Result<Vec<u8>> {
+    let resp = check_status(client.get(uri).send().await?)?;
+    Ok(resp.bytes().await?.to_vec())
+}
+
+/// Fetches a segment, retrying on network and server errors with an
+/// exponential backoff and falling back to the muted copy if it's missing.
+pub async fn fetch_segment(
+    client: &TwitchClient,
+    segment: &Segment,
+    retries: u32,
+) -> Result<Vec<u8>> {
+    let mut attempt = 0;
+    let mut uri = segment.uri.clone();
+    loop {
+        match fetch(client, &uri).await {
+            Ok(bytes) => return Ok(bytes),
+            Err(Error::NotFound(_)) | Err(Error::Status(403, _)) if muted_uri(&uri).is_some() => {
+                uri = muted_uri(&uri).unwrap();
+            }
+            Err(err) if is_retryable(&err) && attempt < retries => {
+                tokio::time::delay_for(Duration::from_millis(500 * 2u64.pow(attempt))).await;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Downloads a single file, such as the video of a clip, to `output`,
+/// retrying on network and server errors. The file is written under a
+/// temporary name first, so `output` only ever exists once complete.
+/// Returns its size.
+pub async fn download_file(
+    client: &TwitchClient,
+    uri: &str,
+    output: &Path,
+    retries: u32,
+) -> Result<u64> {
+    let mut attempt = 0;
+    let bytes = loop {
+        match fetch(client, uri).await {
+            Ok(bytes) => break bytes,
+            Err(err) if is_retryable(&err) && attempt < retries => {
+                tokio::time::delay_for(Duration::from_millis(500 * 2u64.pow(attempt))).await;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    };
+    let tmp = sidecar(output, ".tmp");
+    tokio::fs::write(&tmp, &bytes).await?;
+    tokio::fs::rename(&tmp, output).await?;
+    Ok(bytes.len() as u64)
+}
+
+/// Segments downloaded so far, stored next to the output so that interrupted
+/// downloads can be resumed.
+///
+/// The file has a line of JSON with the VOD and the quality, followed by a
+/// line for every segment completed, appended as soon as it is.
+#[derive(Debug, Default, PartialEq)]
+pub struct Manifest {
+    pub vod_id: String,
+    pub quality: String,
+    /// Completed segments, by segment number.
+    pub segments: BTreeMap<u64, ManifestSegment>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ManifestSegment {
+    pub uri: String,
+    /// Size in bytes of the part file.
+    pub size: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ManifestHeader {
+    vod_id: String,
+    quality: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ManifestLine {
+    number: u64,
+    #[serde(flatten)]
+    segment: ManifestSegment,
+}
+
+impl Manifest {
+    pub fn new(vod_id: &str, quality: &str) -> Manifest {
+        Manifest {
+            vod_id: vod_id.to_string(),
+            quality: quality.to_string(),
+            segments: BTreeMap::new(),
+        }
+    }
+
+    pub fn path(output: &Path) -> PathBuf {
+        sidecar(output, ".manifest.json")
+    }
+
+    /// Returns `None` if there's no manifest or it can't be read. A line cut
+    /// short by a crash is skipped.
+    pub fn load(path: &Path) -> Result<Option<Manifest>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(path)?;
+        let mut lines = content.lines();
+        let header = match lines.next().map(serde_json::from_str::<ManifestHeader>) {
+            Some(Ok(header)) => header,
+            _ => return Ok(None),
+        };
+        let mut manifest = Manifest::new(&header.vod_id, &header.quality);
+        for line in lines.filter_map(|line| serde_json::from_str::<ManifestLine>(line).ok()) {
+            manifest.segments.insert(line.number, line.segment);
+        }
+        Ok(Some(manifest))
+    }
+
+    /// Writes the whole manifest to a temporary file first, so that a crash
+    /// can't leave a half-written header behind.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut content = serde_json::to_string(&ManifestHeader {
+            vod_id: self.vod_id.clone(),
+            quality: self.quality.clone(),
+        })?;
+        content.push('\n');
+        for (number, segment) in &self.segments {
+            content.push_str(&Self::line(*number, segment)?);
+        }
+        let tmp = sidecar(path, ".tmp");
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    fn line(number: u64, segment: &ManifestSegment) -> Result<String> {
+        let line = ManifestLine {
+            number,
+            segment: segment.clone(),
+        };
+        Ok(format!("{}\n", serde_json::to_string(&line)?))
+    }
+
+    /// Records a completed segment, appending it to `file`.
+    fn record(
+        &mut self,
+        file: &mut std::fs::File,
+        number: u64,
+        segment: ManifestSegment,
+    ) -> Result<()> {
+        std::io::Write::write_all(file, Self::line(number, &segment)?.as_bytes())?;
+        self.segments.insert(number, segment);
+        Ok(())
+    }
+
+    /// Forgets the segments that aren't in `segments` with the same URI, or
+    /// whose part file is missing or doesn't have the recorded size, so that
+    /// they are downloaded again.
+    fn verify(&mut self, segments: &[Segment], parts_dir: &Path) {
+        let uris = segments
+            .iter()
+            .map(|segment| (segment.number, segment.uri.as_str()))
+            .collect::<BTreeMap<_, _>>();
+        self.segments.retain(|number, segment| {
+            uris.get(number) == Some(&segment.uri.as_str())
+                && std::fs::metadata(part_path(parts_dir, *number))
+                    .map(|metadata| metadata.len() == segment.size)
+                    .unwrap_or(false)
+        });
+    }
+}
+
+fn part_path(parts_dir: &Path, number: u64) -> PathBuf {
+    parts_dir.join(format!("{}.ts", number))
+}
+
+/// Downloads `segments` with at most `options.workers` concurrent requests and
+/// writes them in order to `output`. If `output` ends in `.mp4` the segments
+/// are remuxed with ffmpeg, which has to be installed.
+///
+/// Every segment is first stored in a `.parts` directory next to `output`,
+/// and recorded in a `.manifest.json` file. Calling `download` again after an
+/// interruption only fetches the segments that are missing or truncated.
+/// Parts left by a download of another VOD or quality are discarded.
+pub async fn download<F>(
+    client: &TwitchClient,
+    vod_id: &str,
+    quality: &str,
+    segments: &[Segment],
+    output: &Path,
+    options: &DownloadOptions,
+    mut on_progress: F,
+) -> Result<()>
+where
+    F: FnMut(&Progress),
+{
+    let remux = output
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("mp4"))
+        .unwrap_or(false);
+    let ts_output = if remux {
+        sidecar(output, ".part.ts")
+    } else {
+        output.to_path_buf()
+    };
+    let parts_dir = &sidecar(output, ".parts");
+    let manifest_path = Manifest::path(output);
+
+    let mut manifest = match Manifest::load(&manifest_path)? {
+        Some(manifest) if manifest.vod_id == vod_id && manifest.quality == quality => manifest,
+        _ => {
+            if parts_dir.exists() {
+                tokio::fs::remove_dir_all(parts_dir).await?;
+            }
+            Manifest::new(vod_id, quality)
+        }
+    };
+    tokio::fs::create_dir_all(parts_dir).await?;
+    manifest.verify(segments, parts_dir);
+    manifest.save(&manifest_path)?;
+    let mut manifest_file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&manifest_path)?;
+
+    let mut progress = Progress::new(segments.len());
+    let missing = segments
+        .iter()
+        .filter(|segment| match manifest.segments.get(&segment.number) {
+            Some(part) => {
+                progress.bytes += part.size;
+                progress.segments_done += 1;
+                false
+            }
+            None => true,
+        })
+        .collect::<Vec<_>>();
+    progress.resumed = progress.segments_done;
+    let mut fetches = stream::iter(missing)
+        .map(|segment| async move {
+            let bytes = fetch_segment(client, segment, options.retries).await?;
+            let tmp = sidecar(&part_path(parts_dir, segment.number), ".tmp");
+            tokio::fs::write(&tmp, &bytes).await?;
+            tokio::fs::rename(&tmp, part_path(parts_dir, segment.number)).await?;
+            let part = ManifestSegment {
+                uri: segment.uri.clone(),
+                size: bytes.len() as u64,
+            };
+            Ok::<_, Error>((segment.number, part))
+        })
+        .buffer_unordered(options.workers.max(1));
+
+    on_progress(&progress);
+    while let Some(result) = fetches.next().await {
+        let (number, part) = result?;
+        progress.bytes += part.size;
+        manifest.record(&mut manifest_file, number, part)?;
+        progress.segments_done += 1;
+        on_progress(&progress);
+    }
+    drop(fetches);
+    drop(manifest_file);
+
+    let mut file = tokio::fs::File::create(&ts_output).await?;
+    for segment in segments {
+        let bytes = tokio::fs::read(part_path(parts_dir, segment.number)).await?;
+        file.write_all(&bytes).await?;
+    }
+    file.flush().await?;
+    drop(file);
+
+    if remux {
+        remux_to_mp4(&ts_output, output).await?;
+        tokio::fs::remove_file(&ts_output).await?;
+    }
+    tokio::fs::remove_dir_all(parts_dir).await?;
+    tokio::fs::remove_file(&manifest_path).await?;
+    Ok(())
+}
+
+async fn remux_to_mp4(input: &Path, output: &Path) -> Result<()> {
+    let status = tokio::process::Command::new("ffmpeg")
+        .arg("-y")
+        .args(["-loglevel", "error"])
+        .arg("-i")
+        .arg(input)
+        .args(["-c", "copy", "-bsf:a", "aac_adtstoasc"])
+        .arg(output)
+        .status()
+        .await
+        .map_err(|err| Error::Remux(format!("can't run ffmpeg: {}", err)))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::Remux(format!("ffmpeg exited with {}", status)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockServer, Route};
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn muted_uris() {
+        assert_eq!(
+            muted_uri("http://a/1.ts").as_deref(),
+            Some("http://a/1-muted.ts")
+        );
+        assert_eq!(
+            muted_uri("http://a/1-unmuted.ts").as_deref(),
+            Some("http://a/1-muted.ts")
+        );
+        assert_eq!(muted_uri("http://a/1-muted.ts"), None);
+    }
+
+    #[test]
+    fn download_single_file() {
+        let output =
+            std::env::temp_dir().join(format!("twitch-cli-test-{}-clip.mp4", std::process::id()));
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let size = runtime
+            .block_on(async {
+                let server = MockServer::start(vec![Route::sequence(
+                    "/clip.mp4",
+                    &[(502, ""), (200, "clip")],
+                )])
+                .await;
+                let uri = format!("{}/clip.mp4", server.url());
+                download_file(&server.client(), &uri, &output, 1).await
+            })
+            .unwrap();
+        assert_eq!(size, 4);
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "clip");
+        assert!(!sidecar(&output, ".tmp").exists());
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn download_in_order() {
+        let output = std::env::temp_dir().join(format!(
+            "twitch-cli-test-{}-download.ts",
+            std::process::id()
+        ));
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let updates = runtime
+            .block_on(async {
+                let server = MockServer::start(vec![
+                    Route::new("/0.ts", "first-"),
+                    Route::new("/1-muted.ts", "second-"),
+                    Route::sequence("/2.ts", &[(503, ""), (200, "third")]),
+                ])
+                .await;
+                let segments = (0..3)
+                    .map(|i| Segment {
+                        number: i,
+                        uri: format!("{}/{}.ts", server.url(), i),
+                        duration: 10.0,
+                    })
+                    .collect::<Vec<_>>();
+                let mut updates = vec![];
+                download(
+                    &server.client(),
+                    "1",
+                    "best",
+                    &segments,
+                    &output,
+                    &DownloadOptions::default(),
+                    |progress| updates.push(progress.segments_done),
+                )
+                .await
+                .map(|_| updates)
+            })
+            .unwrap();
+        assert_eq!(updates, vec![0, 1, 2, 3]);
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "first-second-third"
+        );
+        assert!(!Manifest::path(&output).exists());
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    fn segments(server: &MockServer) -> Vec<Segment> {
+        (0..3)
+            .map(|i| Segment {
+                number: i,
+                uri: format!("{}/{}.ts", server.url(), i),
+                duration: 10.0,
+            })
+            .collect()
+    }
+
+    /// Leaves parts of the first two segments of a download of `quality`,
+    /// the second one truncated, and downloads `segments` in `best`.
+    fn resume_test(name: &str, quality: &str) -> (Progress, String) {
+        let output = std::env::temp_dir().join(format!(
+            "twitch-cli-test-{}-{}.ts",
+            std::process::id(),
+            name
+        ));
+        let parts_dir = sidecar(&output, ".parts");
+        std::fs::create_dir_all(&parts_dir).unwrap();
+        std::fs::write(part_path(&parts_dir, 0), "first-").unwrap();
+        std::fs::write(part_path(&parts_dir, 1), "sec").unwrap();
+
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let progress = runtime
+            .block_on(async {
+                let server = MockServer::start(vec![
+                    Route::new("/0.ts", "FIRST-"),
+                    Route::new("/1.ts", "second-"),
+                    Route::new("/2.ts", "third"),
+                ])
+                .await;
+                let segments = segments(&server);
+                let mut manifest = Manifest::new("1", quality);
+                for (segment, size) in segments.iter().zip(&[6, 7]) {
+                    let part = ManifestSegment {
+                        uri: segment.uri.clone(),
+                        size: *size,
+                    };
+                    manifest.segments.insert(segment.number, part);
+                }
+                manifest.save(&Manifest::path(&output)).unwrap();
+                // A line cut short by a crash.
+                let mut file = std::fs::OpenOptions::new()
+                    .append(true)
+                    .open(Manifest::path(&output))
+                    .unwrap();
+                std::io::Write::write_all(&mut file, b"{\"number\":2,\"ur").unwrap();
+
+                let mut last = None;
+                let options = DownloadOptions {
+                    workers: 1,
+                    retries: 0,
+                };
+                download(
+                    &server.client(),
+                    "1",
+                    "best",
+                    &segments,
+                    &output,
+                    &options,
+                    |progress| last = Some(progress.clone()),
+                )
+                .await
+                .map(|_| last.unwrap())
+            })
+            .unwrap();
+        let content = std::fs::read_to_string(&output).unwrap();
+        assert!(!parts_dir.exists());
+        assert!(!Manifest::path(&output).exists());
+        std::fs::remove_file(&output).unwrap();
+        (progress, content)
+    }
+
+    #[test]
+    fn resume_download() {
+        let (progress, content) = resume_test("resume", "best");
+        assert_eq!(progress.resumed, 1);
+        assert_eq!(progress.segments_done, 3);
+        assert_eq!(content, "first-second-third");
+    }
+
+    #[test]
+    fn discard_parts_of_other_quality() {
+        let (progress, content) = resume_test("requality", "worst");
+        assert_eq!(progress.resumed, 0);
+        assert_eq!(content, "FIRST-second-third");
+    }
+
+    #[test]
+    fn manifest_lines() {
+        let path =
+            std::env::temp_dir().join(format!("twitch-cli-test-{}-manifest", std::process::id()));
+        let mut manifest = Manifest::new("1", "720p60");
+        manifest.save(&path).unwrap();
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        let part = |uri: &str, size| ManifestSegment {
+            uri: uri.to_string(),
+            size,
+        };
+        manifest.record(&mut file, 4, part("4.ts", 10)).unwrap();
+        manifest.record(&mut file, 2, part("2.ts", 20)).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "{\"vod_id\":\"1\",\"quality\":\"720p60\"}
+{\"number\":4,\"uri\":\"4.ts\",\"size\":10}
+{\"number\":2,\"uri\":\"2.ts\",\"size\":20}
+"
+        );
+        assert_eq!(Manifest::load(&path).unwrap(), Some(manifest));
+        std::fs::write(&path, "{\"segments\":{}}").unwrap();
+        assert_eq!(Manifest::load(&path).unwrap(), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
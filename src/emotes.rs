@@ -0,0 +1,236 @@
+//! Emotes of BetterTTV, FrankerFaceZ and 7TV, which Twitch sends as plain
+//! text.
+
+use crate::api::{Comment, EmoteSource, Fragment};
+use crate::client::TwitchClient;
+use crate::error::{check_status, Error, Result};
+use futures::future;
+use std::collections::HashMap;
+
+/// Third-party emotes usable in a channel, by code.
+#[derive(Debug, Default)]
+pub struct ThirdPartyEmotes {
+    emotes: HashMap<String, (EmoteSource, String)>,
+}
+
+/// Lists of emotes in a response.
+type Lists = fn(&serde_json::Value) -> Vec<&serde_json::Value>;
+
+impl ThirdPartyEmotes {
+    /// Fetches the global emotes and the ones of the channel with ID
+    /// `channel_id` from every provider. A provider failing doesn't stop the
+    /// others, so its error is returned along with the emotes found.
+    pub async fn new(client: &TwitchClient, channel_id: &str) -> (ThirdPartyEmotes, Vec<Error>) {
+        let endpoints = client.endpoints();
+        let ffz: Lists = |value| match value["sets"].as_object() {
+            Some(sets) => sets.values().map(|set| &set["emoticons"]).collect(),
+            None => vec![],
+        };
+        // Channel sets come last so that they win over global ones.
+        let sets: Vec<(EmoteSource, String, Lists, &str)> = vec![
+            (
+                EmoteSource::Bttv,
+                format!("{}/cached/emotes/global", endpoints.bttv),
+                |value| vec![value],
+                "code",
+            ),
+            (
+                EmoteSource::Ffz,
+                format!("{}/set/global", endpoints.ffz),
+                ffz,
+                "name",
+            ),
+            (
+                EmoteSource::SevenTv,
+                format!("{}/emote-sets/global", endpoints.seventv),
+                |value| vec![&value["emotes"]],
+                "name",
+            ),
+            (
+                EmoteSource::Bttv,
+                format!("{}/cached/users/twitch/{}", endpoints.bttv, channel_id),
+                |value| vec![&value["channelEmotes"], &value["sharedEmotes"]],
+                "code",
+            ),
+            (
+                EmoteSource::Ffz,
+                format!("{}/room/id/{}", endpoints.ffz, channel_id),
+                ffz,
+                "name",
+            ),
+            (
+                EmoteSource::SevenTv,
+                format!("{}/users/twitch/{}", endpoints.seventv, channel_id),
+                |value| vec![&value["emote_set"]["emotes"]],
+                "name",
+            ),
+        ];
+        let responses =
+            future::join_all(sets.iter().map(|(_, url, _, _)| Self::fetch(client, url))).await;
+
+        let mut emotes = ThirdPartyEmotes::default();
+        let mut errors = vec![];
+        for ((source, _, lists, name), response) in sets.iter().zip(responses) {
+            let value = match response {
+                Ok(Some(value)) => value,
+                Ok(None) => continue,
+                Err(err) => {
+                    errors.push(err);
+                    continue;
+                }
+            };
+            for emote in lists(&value)
+                .into_iter()
+                .filter_map(|list| list.as_array())
+                .flatten()
+            {
+                let id = match &emote["id"] {
+                    serde_json::Value::String(id) => id.clone(),
+                    serde_json::Value::Number(id) => id.to_string(),
+                    _ => continue,
+                };
+                if let Some(code) = emote[*name].as_str() {
+                    emotes.emotes.insert(code.to_string(), (*source, id));
+                }
+            }
+        }
+        (emotes, errors)
+    }
+
+    /// Channels without emotes on a provider are reported as 404s.
+    async fn fetch(client: &TwitchClient, url: &str) -> Result<Option<serde_json::Value>> {
+        match client
+            .get(url)
+            .send()
+            .await
+            .map_err(Error::from)
+            .and_then(check_status)
+        {
+            Ok(resp) => Ok(Some(resp.json().await?)),
+            Err(Error::NotFound(_)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.emotes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.emotes.is_empty()
+    }
+
+    /// Turns the words of the text fragments of `comment` that are codes of
+    /// emotes into emote fragments.
+    pub fn apply(&self, comment: &mut Comment) {
+        if self.emotes.is_empty() {
+            return;
+        }
+        let mut fragments = vec![];
+        let mut begin = 0;
+        for fragment in std::mem::take(&mut comment.fragments) {
+            let text = match fragment {
+                Fragment::Text { text } => text,
+                fragment => {
+                    begin += fragment.text().chars().count();
+                    fragments.push(fragment);
+                    continue;
+                }
+            };
+            for word in text.split_inclusive(char::is_whitespace) {
+                let code = word.trim_end();
+                let length = word.chars().count();
+                match self.emotes.get(code) {
+                    Some((source, id)) if !code.is_empty() => {
+                        let end = begin + code.chars().count() - 1;
+                        fragments.push(Fragment::Emote {
+                            text: code.to_string(),
+                            id: id.clone(),
+                            source: *source,
+                            begin,
+                            end,
+                        });
+                        fragments.push(Fragment::Text {
+                            text: word[code.len()..].to_string(),
+                        });
+                    }
+                    _ => fragments.push(Fragment::Text {
+                        text: word.to_string(),
+                    }),
+                }
+                begin += length;
+            }
+        }
+        comment.fragments = Fragment::merge_text(fragments);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::test_support::comment;
+    use crate::mock::{MockServer, Route};
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn third_party_emotes() {
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        runtime.block_on(async {
+            let server = MockServer::start(vec![
+                Route::new(
+                    "/bttv/cached/emotes/global",
+                    r#"[{"id":"b1","code":"monkaS"},{"id":"b2","code":"KEKW"}]"#,
+                ),
+                Route::new(
+                    "/bttv/cached/users/twitch/7",
+                    r#"{"channelEmotes":[{"id":"b3","code":"dario"}],"sharedEmotes":[{"id":"b4","code":"KEKW"}]}"#,
+                ),
+                Route::new(
+                    "/ffz/set/global",
+                    r#"{"sets":{"3":{"emoticons":[{"id":25927,"name":"CatBag"}]}}}"#,
+                ),
+                Route::with_status("/ffz/room/id/7", 500, "oops"),
+                Route::new(
+                    "/7tv/emote-sets/global",
+                    r#"{"emotes":[{"id":"s1","name":"EZ"}]}"#,
+                ),
+            ])
+            .await;
+            let client = server.client();
+            let (emotes, errors) = ThirdPartyEmotes::new(&client, "7").await;
+            assert_eq!(emotes.len(), 5);
+            assert!(matches!(errors.as_slice(), [Error::Server(500, _)]));
+
+            let text = |text: &str| Fragment::Text {
+                text: text.to_string(),
+            };
+            let mention = Fragment::Mention {
+                text: "@bob".to_string(),
+                login: "bob".to_string(),
+            };
+            let mut comment = comment()
+                .fragments(vec![text("KEKW "), mention.clone(), text(" EZ Clap CatBag")])
+                .build();
+            emotes.apply(&mut comment);
+            let emote = |text: &str, id: &str, source, begin, end| Fragment::Emote {
+                text: text.to_string(),
+                id: id.to_string(),
+                source,
+                begin,
+                end,
+            };
+            assert_eq!(
+                comment.fragments,
+                vec![
+                    emote("KEKW", "b4", EmoteSource::Bttv, 0, 3),
+                    text(" "),
+                    mention,
+                    text(" "),
+                    emote("EZ", "s1", EmoteSource::SevenTv, 10, 11),
+                    text(" Clap "),
+                    emote("CatBag", "25927", EmoteSource::Ffz, 18, 23),
+                ]
+            );
+        });
+    }
+}
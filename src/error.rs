@@ -0,0 +1,161 @@
+use std::fmt;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The request couldn't be sent or the response couldn't be read.
+    Network(reqwest::Error),
+    /// 401, the OAuth token is missing, invalid or revoked.
+    Unauthorized(String),
+    /// 404
+    NotFound(String),
+    /// 429
+    RateLimited(String),
+    /// 5xx
+    Server(u16, String),
+    /// Any other non-success status code.
+    Status(u16, String),
+    UnknownUser(String),
+    UnknownClip(String),
+    /// The channel exists but isn't streaming.
+    Offline(String),
+    /// Twitch returned something that doesn't have the expected shape.
+    MalformedPayload(String),
+    /// The access token of a VOD, a live stream or a clip lacks its signature
+    /// or value.
+    MissingAccessToken(&'static str),
+    Playlist(String),
+    /// No rendition matches the quality selector, the second field lists the
+    /// available ones.
+    UnavailableQuality(String, Vec<String>),
+    /// ffmpeg couldn't be run or failed to remux a download.
+    Remux(String),
+    Auth(String),
+    Config(String),
+    InvalidArgument(String),
+    Io(std::io::Error),
+}
+
+impl Error {
+    pub fn from_status(status: reqwest::StatusCode, url: &str) -> Error {
+        let url = url.to_string();
+        match status.as_u16() {
+            401 => Error::Unauthorized(url),
+            404 => Error::NotFound(url),
+            429 => Error::RateLimited(url),
+            code @ 500..=599 => Error::Server(code, url),
+            code => Error::Status(code, url),
+        }
+    }
+
+    pub fn malformed(what: &str) -> Error {
+        Error::MalformedPayload(what.to_string())
+    }
+
+    /// Exit code of the CLI when it fails with this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::InvalidArgument(_) => 2,
+            Error::Network(_) => 3,
+            Error::Unauthorized(_) | Error::Auth(_) => 4,
+            Error::NotFound(_)
+            | Error::UnknownUser(_)
+            | Error::UnknownClip(_)
+            | Error::Offline(_)
+            | Error::UnavailableQuality(_, _) => 5,
+            Error::RateLimited(_) => 6,
+            Error::Server(_, _) => 7,
+            Error::Status(_, _) | Error::MalformedPayload(_) | Error::MissingAccessToken(_) => 8,
+            Error::Playlist(_) => 9,
+            Error::Config(_) | Error::Io(_) => 10,
+            Error::Remux(_) => 11,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Network(err) => write!(f, "Network error: {}", err),
+            Error::Unauthorized(url) => write!(
+                f,
+                "Twitch refused the credentials for {}, run `twitch-cli auth` to get a new token",
+                url
+            ),
+            Error::NotFound(url) => write!(f, "Twitch returned 404 Not Found for {}", url),
+            Error::RateLimited(url) => write!(f, "Rate limited by Twitch while requesting {}", url),
+            Error::Server(code, url) => write!(f, "Twitch returned {} for {}", code, url),
+            Error::Status(code, url) => {
+                write!(f, "Twitch returned an unexpected {} for {}", code, url)
+            }
+            Error::UnknownUser(username) => {
+                write!(f, "There is not a Twitch user named `{}`", username)
+            }
+            Error::UnknownClip(slug) => write!(f, "There is not a Twitch clip named `{}`", slug),
+            Error::Offline(channel) => write!(f, "`{}` is not live", channel),
+            Error::MalformedPayload(what) => write!(f, "Unexpected response from Twitch: {}", what),
+            Error::MissingAccessToken(field) => write!(
+                f,
+                "The access token returned by Twitch doesn't contain `{}`",
+                field
+            ),
+            Error::Playlist(what) => write!(f, "Can't parse the playlist: {}", what),
+            Error::UnavailableQuality(selector, available) => write!(
+                f,
+                "No rendition matches `{}`, the available ones are: {}",
+                selector,
+                available.join(", ")
+            ),
+            Error::Remux(what) => write!(f, "Can't remux the download: {}", what),
+            Error::Auth(what) => write!(f, "Authentication failed: {}", what),
+            Error::Config(what) => write!(f, "Invalid configuration: {}", what),
+            Error::InvalidArgument(what) => write!(f, "{}", what),
+            Error::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Network(err) => Some(err),
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Error {
+        if err.is_decode() {
+            return Error::MalformedPayload(err.to_string());
+        }
+        match (err.status(), err.url()) {
+            (Some(status), Some(url)) => Error::from_status(status, url.as_str()),
+            _ => Error::Network(err),
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error {
+        Error::MalformedPayload(err.to_string())
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+/// Turns non-success responses into the matching `Error`.
+pub fn check_status(resp: reqwest::Response) -> Result<reqwest::Response> {
+    let status = resp.status();
+    if status.is_success() {
+        Ok(resp)
+    } else {
+        Err(Error::from_status(status, resp.url().as_str()))
+    }
+}
@@ -0,0 +1,11 @@
+//! Helpers for the files written next to outputs.
+
+use std::path::{Path, PathBuf};
+
+/// Path of a file kept next to `path`, named after it with `suffix` appended
+/// (e.g. `vod.mp4.tmp`).
+pub(crate) fn sidecar(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
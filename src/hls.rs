@@ -0,0 +1,878 @@
+//! Parser for the HLS playlists served by Twitch.
+
+use crate::error::{Error, Result};
+use crate::files::sidecar;
+use serde::Serialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Splits an attribute list (`KEY=VALUE,KEY="QUOTED,VALUE"`) into pairs,
+/// removing the quotes around quoted values.
+pub fn parse_attributes(list: &str) -> Vec<(String, String)> {
+    split_attributes(list)
+        .iter()
+        .map(|(key, value)| (key.clone(), unquote(value).to_string()))
+        .collect()
+}
+
+fn unquote(value: &str) -> &str {
+    match value.strip_prefix('"') {
+        Some(quoted) => quoted.strip_suffix('"').unwrap_or(quoted),
+        None => value,
+    }
+}
+
+/// Like `parse_attributes`, but keeps the quotes around quoted values, to
+/// write them back verbatim.
+fn split_attributes(list: &str) -> Vec<(String, String)> {
+    let mut attributes = vec![];
+    let mut rest = list.trim();
+    while !rest.is_empty() {
+        let eq = match rest.find('=') {
+            Some(eq) => eq,
+            None => break,
+        };
+        let key = rest[..eq].trim().to_string();
+        rest = &rest[eq + 1..];
+        let end = match rest.strip_prefix('"') {
+            Some(quoted) => quoted.find('"').map_or(rest.len(), |end| end + 2),
+            None => rest.find(',').unwrap_or(rest.len()),
+        };
+        attributes.push((key, rest[..end].to_string()));
+        rest = rest[end..].trim_start_matches(',').trim_start();
+    }
+    attributes
+}
+
+fn attribute<'a>(attributes: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.as_str())
+}
+
+/// A rendition listed in a master playlist.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Variant {
+    /// Name of the rendition as shown by the Twitch player (e.g. `1080p60
+    /// (source)`), or the video group if there is no name.
+    pub name: String,
+    /// `VIDEO` group of the rendition (e.g. `chunked`, `720p60`,
+    /// `audio_only`).
+    pub group: Option<String>,
+    pub bandwidth: u64,
+    pub resolution: Option<(u32, u32)>,
+    pub frame_rate: Option<f64>,
+    pub codecs: Option<String>,
+    pub uri: String,
+    /// Tags preceding `#EXT-X-STREAM-INF` (e.g. the `#EXT-X-MEDIA` of the
+    /// rendition), kept verbatim.
+    #[serde(skip)]
+    pub tags: Vec<String>,
+    /// Attributes of `#EXT-X-STREAM-INF` as parsed, quotes included. The
+    /// ones not modeled above (e.g. `PROGRAM-ID`) are written back verbatim.
+    #[serde(skip)]
+    pub attributes: Vec<(String, String)>,
+}
+
+impl Variant {
+    pub fn is_audio_only(&self) -> bool {
+        self.group.as_deref() == Some("audio_only")
+    }
+
+    /// Attributes of `#EXT-X-STREAM-INF`, in the order they were parsed.
+    /// The modeled ones are written from the fields, after the others if
+    /// they weren't parsed.
+    fn stream_info(&self) -> String {
+        let mut modeled = vec![
+            ("BANDWIDTH", Some(self.bandwidth.to_string())),
+            ("CODECS", self.codecs.as_ref().map(|x| format!("\"{}\"", x))),
+            (
+                "RESOLUTION",
+                self.resolution.map(|(w, h)| format!("{}x{}", w, h)),
+            ),
+            ("VIDEO", self.group.as_ref().map(|x| format!("\"{}\"", x))),
+            ("FRAME-RATE", self.frame_rate.map(|x| format!("{:.3}", x))),
+        ];
+        let mut attributes = vec![];
+        for (key, value) in &self.attributes {
+            match modeled.iter_mut().find(|(k, _)| k == key) {
+                Some((_, modeled)) => {
+                    if let Some(value) = modeled.take() {
+                        attributes.push(format!("{}={}", key, value));
+                    }
+                }
+                None => attributes.push(format!("{}={}", key, value)),
+            }
+        }
+        for (key, value) in modeled {
+            if let Some(value) = value {
+                attributes.push(format!("{}={}", key, value));
+            }
+        }
+        attributes.join(",")
+    }
+}
+
+/// A master playlist, listing the renditions of a VOD or stream.
+///
+/// Serializing a parsed playlist keeps every tag and attribute, so that it
+/// gives back what was parsed.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct MasterPlaylist {
+    /// Tags before the first rendition (e.g. `#EXT-X-TWITCH-INFO`), kept
+    /// verbatim.
+    #[serde(skip)]
+    pub tags: Vec<String>,
+    pub variants: Vec<Variant>,
+    /// Tags after the last rendition, kept verbatim.
+    #[serde(skip)]
+    pub trailing_tags: Vec<String>,
+}
+
+impl MasterPlaylist {
+    pub fn parse(content: &str) -> Result<MasterPlaylist> {
+        let mut lines = content.lines().map(|line| line.trim());
+        if lines.next() != Some("#EXTM3U") {
+            return Err(Error::Playlist("missing #EXTM3U header".into()));
+        }
+
+        let mut master = MasterPlaylist {
+            tags: vec![],
+            variants: vec![],
+            trailing_tags: vec![],
+        };
+        // NAME of every EXT-X-MEDIA, by GROUP-ID.
+        let mut names = vec![];
+        // Tags of the variant being parsed, until its URI is found.
+        let mut tags: Option<Vec<String>> = None;
+        let mut pending: Option<Vec<(String, String)>> = None;
+        for line in lines {
+            if line.is_empty() {
+                continue;
+            } else if let Some(attributes) = line.strip_prefix("#EXT-X-STREAM-INF:") {
+                pending = Some(split_attributes(attributes));
+                tags.get_or_insert_with(Vec::new);
+            } else if line.starts_with('#') {
+                let media = line.strip_prefix("#EXT-X-MEDIA:");
+                if let Some(attributes) = media {
+                    let attributes = parse_attributes(attributes);
+                    if let (Some(group), Some(name)) = (
+                        attribute(&attributes, "GROUP-ID"),
+                        attribute(&attributes, "NAME"),
+                    ) {
+                        names.push((group.to_string(), name.to_string()));
+                    }
+                }
+                match &mut tags {
+                    Some(tags) => tags.push(line.to_string()),
+                    None if media.is_none() && master.variants.is_empty() => {
+                        master.tags.push(line.to_string())
+                    }
+                    None => tags = Some(vec![line.to_string()]),
+                }
+            } else {
+                let attributes = pending.take().ok_or_else(|| {
+                    Error::Playlist(format!("URI `{}` without #EXT-X-STREAM-INF", line))
+                })?;
+                let mut variant = Self::variant(attributes, line, &names)?;
+                variant.tags = tags.take().unwrap_or_default();
+                master.variants.push(variant);
+            }
+        }
+        if pending.is_some() {
+            return Err(Error::Playlist("missing URI of the last variant".into()));
+        }
+        master.trailing_tags = tags.unwrap_or_default();
+        Ok(master)
+    }
+
+    fn variant(
+        raw_attributes: Vec<(String, String)>,
+        uri: &str,
+        names: &[(String, String)],
+    ) -> Result<Variant> {
+        let attributes = &raw_attributes
+            .iter()
+            .map(|(key, value)| (key.clone(), unquote(value).to_string()))
+            .collect::<Vec<_>>();
+        let group = attribute(attributes, "VIDEO").map(|x| x.to_string());
+        let name = group
+            .as_ref()
+            .and_then(|group| names.iter().find(|(g, _)| g == group))
+            .map(|(_, name)| name.clone())
+            .or_else(|| group.clone())
+            .unwrap_or_else(|| uri.to_string());
+        let bandwidth = attribute(attributes, "BANDWIDTH")
+            .ok_or_else(|| Error::Playlist(format!("variant `{}` has no BANDWIDTH", name)))?;
+
+        Ok(Variant {
+            bandwidth: bandwidth
+                .parse()
+                .map_err(|_| Error::Playlist(format!("invalid BANDWIDTH `{}`", bandwidth)))?,
+            resolution: attribute(attributes, "RESOLUTION").and_then(|resolution| {
+                let mut parts = resolution.split('x');
+                Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+            }),
+            frame_rate: attribute(attributes, "FRAME-RATE").and_then(|x| x.parse().ok()),
+            codecs: attribute(attributes, "CODECS").map(|x| x.to_string()),
+            uri: uri.to_string(),
+            group,
+            name,
+            tags: vec![],
+            attributes: raw_attributes,
+        })
+    }
+
+    /// Picks a rendition. `selector` is a comma separated list of qualities
+    /// tried in order, each of which is either `best`, `worst`,
+    /// `audio_only`, a video group (e.g. `720p60`, `chunked` for the source)
+    /// or the start of a rendition name (e.g. `1080p60`).
+    pub fn select(&self, selector: &str) -> Option<&Variant> {
+        let video = || self.variants.iter().filter(|v| !v.is_audio_only());
+        selector
+            .split(',')
+            .map(|quality| quality.trim())
+            .filter_map(|quality| match quality {
+                "best" => video().max_by_key(|v| v.bandwidth),
+                "worst" => video().min_by_key(|v| v.bandwidth),
+                "source" => self
+                    .variants
+                    .iter()
+                    .find(|v| v.group.as_deref() == Some("chunked")),
+                _ => self
+                    .variants
+                    .iter()
+                    .find(|v| v.group.as_deref() == Some(quality))
+                    .or_else(|| self.variants.iter().find(|v| v.name.starts_with(quality))),
+            })
+            .next()
+    }
+
+    /// Like `select`, but fails listing the available renditions if none
+    /// matches.
+    pub fn select_or_err(&self, selector: &str) -> Result<&Variant> {
+        self.select(selector).ok_or_else(|| {
+            Error::UnavailableQuality(
+                selector.to_string(),
+                self.variants
+                    .iter()
+                    .map(|v| v.group.clone().unwrap_or_else(|| v.name.clone()))
+                    .collect(),
+            )
+        })
+    }
+}
+
+impl fmt::Display for MasterPlaylist {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "#EXTM3U")?;
+        for tag in &self.tags {
+            writeln!(f, "{}", tag)?;
+        }
+        for variant in &self.variants {
+            let has_media = variant
+                .tags
+                .iter()
+                .any(|tag| tag.starts_with("#EXT-X-MEDIA:"));
+            if let (Some(group), false) = (&variant.group, has_media) {
+                writeln!(
+                    f,
+                    "#EXT-X-MEDIA:TYPE=VIDEO,GROUP-ID=\"{}\",NAME=\"{}\"",
+                    group, variant.name
+                )?;
+            }
+            for tag in &variant.tags {
+                writeln!(f, "{}", tag)?;
+            }
+            writeln!(f, "#EXT-X-STREAM-INF:{}", variant.stream_info())?;
+            writeln!(f, "{}", variant.uri)?;
+        }
+        for tag in &self.trailing_tags {
+            writeln!(f, "{}", tag)?;
+        }
+        Ok(())
+    }
+}
+
+/// `#EXT-X-BYTERANGE` of a segment: `length` bytes starting at `offset`, or
+/// right after the previous range if `offset` is `None`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ByteRange {
+    pub length: u64,
+    pub offset: Option<u64>,
+}
+
+impl ByteRange {
+    fn parse(value: &str) -> Result<ByteRange> {
+        let invalid = || Error::Playlist(format!("invalid #EXT-X-BYTERANGE `{}`", value));
+        let mut parts = value.splitn(2, '@');
+        let length = parts
+            .next()
+            .and_then(|length| length.parse().ok())
+            .ok_or_else(invalid)?;
+        let offset = match parts.next() {
+            Some(offset) => Some(offset.parse().map_err(|_| invalid())?),
+            None => None,
+        };
+        Ok(ByteRange { length, offset })
+    }
+}
+
+impl fmt::Display for ByteRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.offset {
+            Some(offset) => write!(f, "{}@{}", self.length, offset),
+            None => write!(f, "{}", self.length),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MediaSegment {
+    pub uri: String,
+    pub duration: f64,
+    /// Text after the duration in `#EXTINF`, usually empty.
+    pub title: String,
+    pub byte_range: Option<ByteRange>,
+    /// Tags of the segment other than `#EXTINF` and `#EXT-X-BYTERANGE`,
+    /// e.g. `#EXT-X-DISCONTINUITY`, kept verbatim and in playlist order.
+    pub tags: Vec<String>,
+}
+
+impl MediaSegment {
+    /// Whether the segment is preceded by `#EXT-X-DISCONTINUITY`.
+    pub fn discontinuity(&self) -> bool {
+        self.tags.iter().any(|tag| tag == "#EXT-X-DISCONTINUITY")
+    }
+
+    pub fn program_date_time(&self) -> Option<&str> {
+        self.tags
+            .iter()
+            .find_map(|tag| tag.strip_prefix("#EXT-X-PROGRAM-DATE-TIME:"))
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.uri.ends_with("-muted.ts")
+    }
+}
+
+/// `#EXT-X-START`, where players should start playing a playlist.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StartOffset {
+    pub time_offset: f64,
+    pub precise: bool,
+}
+
+/// Exact cut points of a playlist trimmed by `MediaPlaylist::trim`. The
+/// playlist starts and ends on segment boundaries, so a trimmer has to drop
+/// `start_offset` seconds from its start and keep only up to `end_offset`
+/// seconds to get exactly the requested range.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Cut {
+    /// Requested start, in seconds from the start of the original playlist.
+    pub start: f64,
+    /// Requested end, clamped to the duration of the original playlist.
+    pub end: f64,
+    /// Media sequence number of the first kept segment.
+    pub first_segment: u64,
+    /// Media sequence number of the last kept segment.
+    pub last_segment: u64,
+    /// Start of the first kept segment in the original playlist.
+    pub segments_start: f64,
+    /// End of the last kept segment in the original playlist.
+    pub segments_end: f64,
+    /// Position of `start` in the trimmed playlist, i.e. in its first segment.
+    pub start_offset: f64,
+    /// Position of `end` in the trimmed playlist.
+    pub end_offset: f64,
+}
+
+impl Cut {
+    /// Path of the JSON file describing the cut points of `output`.
+    pub fn path(output: &Path) -> PathBuf {
+        sidecar(output, ".cut.json")
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Seconds of the broadcast before the first segment of a playlist.
+const TWITCH_ELAPSED: &str = "#EXT-X-TWITCH-ELAPSED-SECS:";
+/// Seconds of the broadcast up to the end of a playlist.
+const TWITCH_TOTAL: &str = "#EXT-X-TWITCH-TOTAL-SECS:";
+
+/// Rounds to milliseconds, the precision of the durations in Twitch playlists,
+/// to hide the errors accumulated by summing them.
+fn millis(seconds: f64) -> f64 {
+    (seconds * 1000.0).round() / 1000.0
+}
+
+/// A media playlist, listing the segments of a rendition.
+///
+/// Serializing a parsed playlist keeps every segment and tag, but writes the
+/// known tags of the playlist in a fixed order and durations with three
+/// decimals, like Twitch does, so that serializing is stable across round
+/// trips.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaPlaylist {
+    pub version: Option<u32>,
+    pub target_duration: u64,
+    pub media_sequence: u64,
+    pub playlist_type: Option<String>,
+    pub start: Option<StartOffset>,
+    /// Tags of the playlist not modeled here (e.g. `#EXT-X-TWITCH-*`), kept
+    /// verbatim.
+    pub tags: Vec<String>,
+    pub segments: Vec<MediaSegment>,
+    /// Unknown tags after the last segment, kept verbatim.
+    pub trailing_tags: Vec<String>,
+    pub end_list: bool,
+}
+
+fn parse_number<T: std::str::FromStr>(tag: &str, value: &str) -> Result<T> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| Error::Playlist(format!("invalid {} `{}`", tag, value)))
+}
+
+impl MediaPlaylist {
+    pub fn parse(content: &str) -> Result<MediaPlaylist> {
+        let mut lines = content.lines().map(|line| line.trim());
+        if lines.next() != Some("#EXTM3U") {
+            return Err(Error::Playlist("missing #EXTM3U header".into()));
+        }
+
+        let mut playlist = MediaPlaylist {
+            version: None,
+            target_duration: 0,
+            media_sequence: 0,
+            playlist_type: None,
+            start: None,
+            tags: vec![],
+            segments: vec![],
+            trailing_tags: vec![],
+            end_list: false,
+        };
+        let mut target_duration = None;
+        // Tags of the segment being parsed, until its URI is found.
+        let mut pending: Option<MediaSegment> = None;
+        for line in lines {
+            let segment = MediaSegment::default;
+            if line.is_empty() {
+                continue;
+            } else if let Some(value) = line.strip_prefix("#EXT-X-VERSION:") {
+                playlist.version = Some(parse_number("#EXT-X-VERSION", value)?);
+            } else if let Some(value) = line.strip_prefix("#EXT-X-TARGETDURATION:") {
+                target_duration = Some(parse_number("#EXT-X-TARGETDURATION", value)?);
+            } else if let Some(value) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+                playlist.media_sequence = parse_number("#EXT-X-MEDIA-SEQUENCE", value)?;
+            } else if let Some(value) = line.strip_prefix("#EXT-X-PLAYLIST-TYPE:") {
+                playlist.playlist_type = Some(value.to_string());
+            } else if let Some(value) = line.strip_prefix("#EXT-X-START:") {
+                let attributes = parse_attributes(value);
+                let time_offset = attribute(&attributes, "TIME-OFFSET")
+                    .ok_or_else(|| Error::Playlist("#EXT-X-START without TIME-OFFSET".into()))?;
+                playlist.start = Some(StartOffset {
+                    time_offset: parse_number("TIME-OFFSET", time_offset)?,
+                    precise: attribute(&attributes, "PRECISE") == Some("YES"),
+                });
+            } else if line == "#EXT-X-ENDLIST" {
+                playlist.end_list = true;
+            } else if let Some(value) = line.strip_prefix("#EXTINF:") {
+                let mut parts = value.splitn(2, ',');
+                let pending = pending.get_or_insert_with(segment);
+                pending.duration = parse_number("#EXTINF", parts.next().unwrap_or(""))?;
+                pending.title = parts.next().unwrap_or("").to_string();
+            } else if let Some(value) = line.strip_prefix("#EXT-X-BYTERANGE:") {
+                pending.get_or_insert_with(segment).byte_range = Some(ByteRange::parse(value)?);
+            } else if line == "#EXT-X-DISCONTINUITY"
+                || line.starts_with("#EXT-X-PROGRAM-DATE-TIME:")
+            {
+                // Always tags of a segment, even before the first one.
+                pending
+                    .get_or_insert_with(segment)
+                    .tags
+                    .push(line.to_string());
+            } else if line.starts_with('#') {
+                if pending.is_none() && playlist.segments.is_empty() {
+                    playlist.tags.push(line.to_string());
+                } else {
+                    pending
+                        .get_or_insert_with(segment)
+                        .tags
+                        .push(line.to_string());
+                }
+            } else {
+                let mut segment = pending
+                    .take()
+                    .ok_or_else(|| Error::Playlist(format!("URI `{}` without #EXTINF", line)))?;
+                segment.uri = line.to_string();
+                playlist.segments.push(segment);
+            }
+        }
+        if let Some(mut pending) = pending {
+            playlist.trailing_tags = std::mem::take(&mut pending.tags);
+            if pending != MediaSegment::default() {
+                return Err(Error::Playlist("missing URI of the last segment".into()));
+            }
+        }
+        playlist.target_duration =
+            target_duration.unwrap_or_else(|| playlist.computed_target_duration());
+        Ok(playlist)
+    }
+
+    /// Duration of the longest segment rounded up, as `#EXT-X-TARGETDURATION`
+    /// requires.
+    pub fn computed_target_duration(&self) -> u64 {
+        self.segments
+            .iter()
+            .map(|segment| segment.duration.ceil() as u64)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.segments.iter().map(|segment| segment.duration).sum()
+    }
+
+    /// Keeps only the segments that overlap the range between `start` and
+    /// `end` seconds, including the ones containing `start` and `end`, and
+    /// sets `#EXT-X-START` to the position of `start` in what is left.
+    /// `start` defaults to the start of the playlist and `end` to its end.
+    pub fn trim(&mut self, start: Option<f64>, end: Option<f64>) -> Result<Cut> {
+        let duration = self.duration();
+        let start = start.unwrap_or(0.0).max(0.0);
+        let end = end.unwrap_or(duration).min(duration);
+        let empty = || {
+            Error::InvalidArgument(format!(
+                "the range from {:.3} to {:.3} seconds is empty, the playlist lasts {:.3} seconds",
+                start, end, duration
+            ))
+        };
+        if start >= end {
+            return Err(empty());
+        }
+
+        let mut position = 0.0;
+        let mut kept: Option<(usize, f64)> = None;
+        let mut last = (0, 0.0);
+        for (i, segment) in self.segments.iter().enumerate() {
+            let segment_end = position + segment.duration;
+            if segment_end > start && position < end {
+                kept.get_or_insert((i, position));
+                last = (i, segment_end);
+            }
+            position = segment_end;
+        }
+        let (first, segments_start) = kept.ok_or_else(empty)?;
+        let (last, segments_end) = last;
+
+        self.segments.truncate(last + 1);
+        self.segments.drain(..first);
+        self.media_sequence += first as u64;
+        self.target_duration = self.computed_target_duration();
+        let cut = Cut {
+            start: millis(start),
+            end: millis(end),
+            first_segment: self.media_sequence,
+            last_segment: self.media_sequence + (last - first) as u64,
+            segments_start: millis(segments_start),
+            segments_end: millis(segments_end),
+            start_offset: millis(start - segments_start),
+            end_offset: millis(end - segments_start),
+        };
+        self.start = Some(StartOffset {
+            time_offset: cut.start_offset,
+            precise: true,
+        });
+
+        // Where Twitch says the playlist is in the broadcast, moved to the
+        // kept segments.
+        let elapsed = self
+            .tags
+            .iter()
+            .find_map(|tag| tag.strip_prefix(TWITCH_ELAPSED)?.parse::<f64>().ok())
+            .unwrap_or(0.0)
+            + cut.segments_start;
+        let total = elapsed + self.duration();
+        for tag in &mut self.tags {
+            if tag.starts_with(TWITCH_ELAPSED) {
+                *tag = format!("{}{:.3}", TWITCH_ELAPSED, millis(elapsed));
+            } else if tag.starts_with(TWITCH_TOTAL) {
+                *tag = format!("{}{:.3}", TWITCH_TOTAL, millis(total));
+            }
+        }
+        Ok(cut)
+    }
+}
+
+impl fmt::Display for MediaPlaylist {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "#EXTM3U")?;
+        if let Some(version) = self.version {
+            writeln!(f, "#EXT-X-VERSION:{}", version)?;
+        }
+        writeln!(f, "#EXT-X-TARGETDURATION:{}", self.target_duration)?;
+        if let Some(playlist_type) = &self.playlist_type {
+            writeln!(f, "#EXT-X-PLAYLIST-TYPE:{}", playlist_type)?;
+        }
+        writeln!(f, "#EXT-X-MEDIA-SEQUENCE:{}", self.media_sequence)?;
+        if let Some(start) = self.start {
+            write!(f, "#EXT-X-START:TIME-OFFSET={:.3}", start.time_offset)?;
+            if start.precise {
+                write!(f, ",PRECISE=YES")?;
+            }
+            writeln!(f)?;
+        }
+        for tag in &self.tags {
+            writeln!(f, "{}", tag)?;
+        }
+        for segment in &self.segments {
+            for tag in &segment.tags {
+                writeln!(f, "{}", tag)?;
+            }
+            writeln!(f, "#EXTINF:{:.3},{}", segment.duration, segment.title)?;
+            if let Some(byte_range) = segment.byte_range {
+                writeln!(f, "#EXT-X-BYTERANGE:{}", byte_range)?;
+            }
+            writeln!(f, "{}", segment.uri)?;
+        }
+        for tag in &self.trailing_tags {
+            writeln!(f, "{}", tag)?;
+        }
+        if self.end_list {
+            writeln!(f, "#EXT-X-ENDLIST")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MASTER: &str = r#"#EXTM3U
+#EXT-X-TWITCH-INFO:ORIGIN="s3",B="false",REGION="EU",USER-IP="127.0.0.1",SERVING-ID="abc",CLUSTER="metro_vod",USER-COUNTRY="IT",MANIFEST-CLUSTER="metro_vod"
+#EXT-X-MEDIA:TYPE=VIDEO,GROUP-ID="chunked",NAME="1080p60 (source)",AUTOSELECT=YES,DEFAULT=YES
+#EXT-X-STREAM-INF:PROGRAM-ID=1,BANDWIDTH=6155211,CODECS="avc1.64002A,mp4a.40.2",RESOLUTION=1920x1080,VIDEO="chunked",FRAME-RATE=60.000
+https://vod.example/chunked/index-dvr.m3u8
+#EXT-X-MEDIA:TYPE=VIDEO,GROUP-ID="720p60",NAME="720p60",AUTOSELECT=YES,DEFAULT=YES
+#EXT-X-STREAM-INF:PROGRAM-ID=1,BANDWIDTH=3422999,CODECS="avc1.4D401F,mp4a.40.2",RESOLUTION=1280x720,VIDEO="720p60",FRAME-RATE=60.000
+https://vod.example/720p60/index-dvr.m3u8
+#EXT-X-MEDIA:TYPE=VIDEO,GROUP-ID="160p30",NAME="160p",AUTOSELECT=YES,DEFAULT=YES
+#EXT-X-STREAM-INF:PROGRAM-ID=1,BANDWIDTH=288999,CODECS="avc1.4D400C,mp4a.40.2",RESOLUTION=284x160,VIDEO="160p30",FRAME-RATE=30.000
+https://vod.example/160p30/index-dvr.m3u8
+#EXT-X-MEDIA:TYPE=VIDEO,GROUP-ID="audio_only",NAME="Audio Only",AUTOSELECT=NO,DEFAULT=NO
+#EXT-X-STREAM-INF:PROGRAM-ID=1,BANDWIDTH=160000,CODECS="mp4a.40.2",VIDEO="audio_only"
+https://vod.example/audio_only/index-dvr.m3u8
+"#;
+
+    const LIVE_MASTER: &str = r#"#EXTM3U
+#EXT-X-TWITCH-INFO:NODE="video-edge-c2a0b4.fra05",MANIFEST-NODE-TYPE="weaver_cluster",MANIFEST-NODE="video-weaver.fra05",SUPPRESS="true",SERVER-TIME="1588356000.00",TRANSCODESTACK="2017TranscodeX264_V2",USER-IP="127.0.0.1",SERVING-ID="abc",CLUSTER="fra05",ABS="false",VIDEO-SESSION-ID="123",BROADCAST-ID="40952121085",STREAM-TIME="3600.0",B="false",USER-COUNTRY="IT",MANIFEST-CLUSTER="fra05",ORIGIN="fra05"
+#EXT-X-MEDIA:TYPE=VIDEO,GROUP-ID="chunked",NAME="1080p60 (source)",AUTOSELECT=YES,DEFAULT=YES
+#EXT-X-STREAM-INF:BANDWIDTH=8534030,RESOLUTION=1920x1080,CODECS="avc1.4D402A,mp4a.40.2",VIDEO="chunked",FRAME-RATE=60.000
+https://video-weaver.fra05.hls.ttvnw.net/v1/playlist/abc.m3u8
+#EXT-X-MEDIA:TYPE=VIDEO,GROUP-ID="audio_only",NAME="audio_only",AUTOSELECT=NO,DEFAULT=NO
+#EXT-X-STREAM-INF:BANDWIDTH=160000,CODECS="mp4a.40.2",VIDEO="audio_only"
+https://video-weaver.fra05.hls.ttvnw.net/v1/playlist/def.m3u8
+"#;
+
+    const MEDIA: &str = "#EXTM3U
+#EXT-X-VERSION:3
+#EXT-X-TARGETDURATION:10
+#EXT-X-PLAYLIST-TYPE:EVENT
+#EXT-X-MEDIA-SEQUENCE:0
+#EXT-X-TWITCH-ELAPSED-SECS:0.000
+#EXT-X-TWITCH-TOTAL-SECS:34.500
+#EXT-X-PROGRAM-DATE-TIME:2020-05-01T18:00:00.000Z
+#EXTINF:10.000,
+0.ts
+#EXTINF:10.000,
+1-muted.ts
+#EXT-X-TWITCH-DISCONTINUITY
+#EXT-X-DISCONTINUITY
+#EXTINF:10.000,live
+#EXT-X-BYTERANGE:1024@512
+2-unmuted.ts
+#EXTINF:4.500,
+3.ts
+#EXT-X-ENDLIST
+";
+
+    #[test]
+    fn attributes() {
+        assert_eq!(
+            parse_attributes(r#"BANDWIDTH=1,CODECS="a,b",VIDEO="x""#),
+            vec![
+                ("BANDWIDTH".to_string(), "1".to_string()),
+                ("CODECS".to_string(), "a,b".to_string()),
+                ("VIDEO".to_string(), "x".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_master() {
+        let master = MasterPlaylist::parse(MASTER).unwrap();
+        assert_eq!(master.variants.len(), 4);
+        let source = &master.variants[0];
+        assert_eq!(source.name, "1080p60 (source)");
+        assert_eq!(source.group.as_deref(), Some("chunked"));
+        assert_eq!(source.bandwidth, 6155211);
+        assert_eq!(source.resolution, Some((1920, 1080)));
+        assert_eq!(source.frame_rate, Some(60.0));
+        assert_eq!(source.codecs.as_deref(), Some("avc1.64002A,mp4a.40.2"));
+        assert_eq!(source.uri, "https://vod.example/chunked/index-dvr.m3u8");
+        assert!(master.variants[3].is_audio_only());
+        assert_eq!(master.variants[3].resolution, None);
+    }
+
+    #[test]
+    fn select_quality() {
+        let master = MasterPlaylist::parse(MASTER).unwrap();
+        let group = |selector| master.select(selector).and_then(|v| v.group.as_deref());
+        assert_eq!(group("best"), Some("chunked"));
+        assert_eq!(group("source"), Some("chunked"));
+        assert_eq!(group("worst"), Some("160p30"));
+        assert_eq!(group("audio_only"), Some("audio_only"));
+        assert_eq!(group("720p60"), Some("720p60"));
+        assert_eq!(group("160p"), Some("160p30"));
+        assert_eq!(group("480p30,720p60,best"), Some("720p60"));
+        assert_eq!(group("480p30"), None);
+        assert!(master.select_or_err("480p30").is_err());
+    }
+
+    #[test]
+    fn parse_media() {
+        let media = MediaPlaylist::parse(MEDIA).unwrap();
+        assert_eq!(media.version, Some(3));
+        assert_eq!(media.target_duration, 10);
+        assert_eq!(media.playlist_type.as_deref(), Some("EVENT"));
+        assert_eq!(media.tags.len(), 2);
+        assert_eq!(media.segments.len(), 4);
+        assert!(media.end_list);
+        assert_eq!(media.duration(), 34.5);
+
+        let first = &media.segments[0];
+        assert_eq!(first.program_date_time(), Some("2020-05-01T18:00:00.000Z"));
+        assert!(media.segments[1].is_muted());
+        let third = &media.segments[2];
+        assert!(third.discontinuity());
+        assert_eq!(
+            third.tags,
+            vec!["#EXT-X-TWITCH-DISCONTINUITY", "#EXT-X-DISCONTINUITY"]
+        );
+        assert_eq!(third.title, "live");
+        assert_eq!(
+            third.byte_range,
+            Some(ByteRange {
+                length: 1024,
+                offset: Some(512)
+            })
+        );
+        assert!(!third.is_muted());
+    }
+
+    #[test]
+    fn media_round_trip() {
+        let media = MediaPlaylist::parse(MEDIA).unwrap();
+        assert_eq!(media.to_string(), MEDIA);
+        assert_eq!(MediaPlaylist::parse(&media.to_string()).unwrap(), media);
+
+        // Segment tags keep their order, whether known or not.
+        let content = "#EXTM3U
+#EXT-X-TARGETDURATION:10
+#EXT-X-MEDIA-SEQUENCE:0
+#EXT-X-PROGRAM-DATE-TIME:2020-05-01T18:00:00.000Z
+#EXT-X-TWITCH-DISCONTINUITY
+#EXT-X-DISCONTINUITY
+#EXT-X-TWITCH-MUTED
+#EXTINF:10.000,
+0.ts
+";
+        let media = MediaPlaylist::parse(content).unwrap();
+        assert!(media.segments[0].discontinuity());
+        assert_eq!(media.to_string(), content);
+    }
+
+    #[test]
+    fn computed_target_duration() {
+        let media =
+            MediaPlaylist::parse("#EXTM3U\n#EXTINF:10.2,\n0.ts\n#EXTINF:4.0,\n1.ts\n").unwrap();
+        assert_eq!(media.target_duration, 11);
+        assert!(!media.end_list);
+        assert!(MediaPlaylist::parse("#EXTM3U\n#EXTINF:10.000,\n").is_err());
+    }
+
+    #[test]
+    fn master_round_trip() {
+        let master = MasterPlaylist::parse(MASTER).unwrap();
+        assert_eq!(master.tags.len(), 1);
+        assert_eq!(master.to_string(), MASTER);
+        assert_eq!(MasterPlaylist::parse(&master.to_string()).unwrap(), master);
+        let live = MasterPlaylist::parse(LIVE_MASTER).unwrap();
+        assert_eq!(live.variants[0].resolution, Some((1920, 1080)));
+        assert_eq!(live.to_string(), LIVE_MASTER);
+
+        let mut variant = master.variants[1].clone();
+        variant.tags.clear();
+        variant.attributes.clear();
+        variant.bandwidth = 1;
+        let built = MasterPlaylist {
+            tags: vec![],
+            variants: vec![variant],
+            trailing_tags: vec![],
+        };
+        assert_eq!(
+            built.to_string(),
+            "#EXTM3U
+#EXT-X-MEDIA:TYPE=VIDEO,GROUP-ID=\"720p60\",NAME=\"720p60\"
+#EXT-X-STREAM-INF:BANDWIDTH=1,CODECS=\"avc1.4D401F,mp4a.40.2\",RESOLUTION=1280x720,VIDEO=\"720p60\",FRAME-RATE=60.000
+https://vod.example/720p60/index-dvr.m3u8
+"
+        );
+    }
+
+    #[test]
+    fn trim() {
+        let mut media = MediaPlaylist::parse(MEDIA).unwrap();
+        let cut = media.trim(Some(15.0), Some(25.25)).unwrap();
+        assert_eq!(
+            cut,
+            Cut {
+                start: 15.0,
+                end: 25.25,
+                first_segment: 1,
+                last_segment: 2,
+                segments_start: 10.0,
+                segments_end: 30.0,
+                start_offset: 5.0,
+                end_offset: 15.25,
+            }
+        );
+        assert_eq!(media.media_sequence, 1);
+        assert_eq!(media.segments.len(), 2);
+        assert!(media.segments[0].is_muted());
+        let serialized = media.to_string();
+        assert!(serialized.contains("#EXT-X-START:TIME-OFFSET=5.000,PRECISE=YES\n"));
+        assert!(serialized.contains("#EXT-X-TWITCH-ELAPSED-SECS:10.000\n"));
+        assert!(serialized.contains("#EXT-X-TWITCH-TOTAL-SECS:30.000\n"));
+        assert_eq!(MediaPlaylist::parse(&serialized).unwrap(), media);
+
+        // Ranges on segment boundaries don't pull in the neighbouring segments.
+        let mut media = MediaPlaylist::parse(MEDIA).unwrap();
+        let cut = media.trim(Some(10.0), Some(20.0)).unwrap();
+        assert_eq!((cut.first_segment, cut.last_segment), (1, 1));
+        assert_eq!(cut.start_offset, 0.0);
+
+        let mut media = MediaPlaylist::parse(MEDIA).unwrap();
+        let cut = media.trim(Some(32.0), None).unwrap();
+        assert_eq!((cut.first_segment, cut.end), (3, 34.5));
+        assert!(media.trim(Some(40.0), None).is_err());
+    }
+}
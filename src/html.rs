@@ -0,0 +1,474 @@
+//! Chat replays as a single HTML page that works offline.
+
+use crate::api::{ChatBadges, Comment, EmoteSource, Fragment};
+use crate::client::TwitchClient;
+use crate::config::Endpoints;
+use crate::error::{check_status, Error, Result};
+use futures::stream::{self, StreamExt};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+/// Images downloaded at once.
+const CONCURRENT_IMAGES: usize = 8;
+
+/// URL of the smallest image of an emote.
+pub fn emote_url(endpoints: &Endpoints, source: EmoteSource, id: &str) -> String {
+    match source {
+        EmoteSource::Twitch => format!(
+            "{}/emoticons/v2/{}/default/dark/1.0",
+            endpoints.twitch_cdn, id
+        ),
+        EmoteSource::Bttv => format!("{}/emote/{}/1x", endpoints.bttv_cdn, id),
+        EmoteSource::Ffz => format!("{}/emote/{}/1", endpoints.ffz_cdn, id),
+        EmoteSource::SevenTv => format!("{}/emote/{}/1x.webp", endpoints.seventv_cdn, id),
+    }
+}
+
+/// URLs of the images of the badges and emotes in `comments`.
+pub fn image_urls(endpoints: &Endpoints, comments: &[Comment], badges: &ChatBadges) -> Vec<String> {
+    let mut urls = vec![];
+    for comment in comments {
+        urls.extend(
+            comment
+                .user
+                .badges
+                .iter()
+                .filter_map(|badge| badges.image(badge))
+                .map(str::to_string),
+        );
+        urls.extend(
+            comment
+                .fragments
+                .iter()
+                .filter_map(|fragment| match fragment {
+                    Fragment::Emote { id, source, .. } => Some(emote_url(endpoints, *source, id)),
+                    _ => None,
+                }),
+        );
+    }
+    urls.sort();
+    urls.dedup();
+    urls
+}
+
+/// Images embedded in the page, by URL. Each one is written once, as the
+/// background of a CSS class that every occurrence of it uses.
+#[derive(Debug, Default)]
+pub struct Images {
+    images: BTreeMap<String, Image>,
+}
+
+#[derive(Debug)]
+struct Image {
+    /// `data:` URI of the image.
+    data: String,
+    /// Width and height in pixels, if the format is one `image_size` reads.
+    size: Option<(u32, u32)>,
+}
+
+/// Width and height of a PNG, GIF or WebP image.
+fn image_size(bytes: &[u8]) -> Option<(u32, u32)> {
+    // Reads `len` bytes at `at` as a little-endian integer.
+    let le = |at: usize, len: usize| {
+        let bytes = bytes.get(at..at + len)?;
+        Some(bytes.iter().rev().fold(0, |n, &b| n << 8 | u32::from(b)))
+    };
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        let be = |at: usize| Some(le(at, 4)?.swap_bytes());
+        Some((be(16)?, be(20)?))
+    } else if bytes.starts_with(b"GIF8") {
+        Some((le(6, 2)?, le(8, 2)?))
+    } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        match bytes.get(12..16)? {
+            b"VP8 " => Some((le(26, 2)? & 0x3fff, le(28, 2)? & 0x3fff)),
+            b"VP8L" => {
+                let bits = le(21, 4)?;
+                Some(((bits & 0x3fff) + 1, (bits >> 14 & 0x3fff) + 1))
+            }
+            b"VP8X" => Some((le(24, 3)? + 1, le(27, 3)? + 1)),
+            _ => None,
+        }
+    } else {
+        None
+    }
+}
+
+impl Images {
+    /// Downloads every image in `urls`. Images that can't be downloaded are
+    /// left out, and their errors returned.
+    pub async fn fetch(client: &TwitchClient, urls: &[String]) -> (Images, Vec<Error>) {
+        let results = stream::iter(urls)
+            .map(|url| async move { (url, Self::fetch_one(client, url).await) })
+            .buffer_unordered(CONCURRENT_IMAGES)
+            .collect::<Vec<_>>()
+            .await;
+        let mut images = Images::default();
+        let mut errors = vec![];
+        for (url, result) in results {
+            match result {
+                Ok(image) => {
+                    images.images.insert(url.clone(), image);
+                }
+                Err(err) => errors.push(err),
+            }
+        }
+        (images, errors)
+    }
+
+    async fn fetch_one(client: &TwitchClient, url: &str) -> Result<Image> {
+        let resp = check_status(client.get(url).send().await?)?;
+        let content_type = resp
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| value.starts_with("image/"))
+            .map(str::to_string)
+            .unwrap_or_else(|| {
+                if url.ends_with(".webp") {
+                    "image/webp".to_string()
+                } else if url.ends_with(".gif") {
+                    "image/gif".to_string()
+                } else {
+                    "image/png".to_string()
+                }
+            });
+        let bytes = resp.bytes().await?;
+        Ok(Image {
+            data: format!("data:{};base64,{}", content_type, base64::encode(&bytes)),
+            size: image_size(&bytes),
+        })
+    }
+
+    /// `data:` URI of the image of `url`.
+    pub fn get(&self, url: &str) -> Option<&str> {
+        self.images.get(url).map(|image| image.data.as_str())
+    }
+
+    /// Index of the CSS class of each image, by URL.
+    fn classes(&self) -> HashMap<&str, usize> {
+        self.images
+            .keys()
+            .enumerate()
+            .map(|(index, url)| (url.as_str(), index))
+            .collect()
+    }
+
+    /// CSS rules of the classes of the images.
+    fn style(&self) -> String {
+        let mut style = String::new();
+        for (index, image) in self.images.values().enumerate() {
+            let size = image
+                .size
+                .map(|(width, height)| format!("width:{}px;height:{}px;", width, height))
+                .unwrap_or_default();
+            let _ = writeln!(
+                style,
+                ".img.i{}{{{}background-image:url({})}}",
+                index, size, image.data
+            );
+        }
+        style
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Element showing the image of `url` if it was downloaded, `fallback`
+/// otherwise.
+fn image(
+    classes: &HashMap<&str, usize>,
+    url: Option<&str>,
+    class: &str,
+    alt: &str,
+    fallback: &str,
+) -> String {
+    match url.and_then(|url| classes.get(url)) {
+        Some(index) => format!(
+            "<span class=\"img {} i{}\" role=\"img\" aria-label=\"{}\" title=\"{}\"></span>",
+            class,
+            index,
+            escape(alt),
+            escape(alt)
+        ),
+        None => fallback.to_string(),
+    }
+}
+
+fn message(endpoints: &Endpoints, comment: &Comment, classes: &HashMap<&str, usize>) -> String {
+    if comment.fragments.is_empty() {
+        return escape(&comment.message);
+    }
+    comment
+        .fragments
+        .iter()
+        .map(|fragment| match fragment {
+            Fragment::Text { text } => escape(text),
+            Fragment::Emote {
+                text, id, source, ..
+            } => {
+                let fallback = format!("<span class=\"emote\">{}</span>", escape(text));
+                let url = emote_url(endpoints, *source, id);
+                image(classes, Some(&url), "emote", text, &fallback)
+            }
+            Fragment::Mention { text, .. } => {
+                format!("<span class=\"mention\">{}</span>", escape(text))
+            }
+            Fragment::Cheer { text, .. } => {
+                format!("<span class=\"cheer\">{}</span>", escape(text))
+            }
+            Fragment::Link { text, url } => format!(
+                "<a href=\"{}\" rel=\"noopener noreferrer\">{}</a>",
+                escape(url),
+                escape(text)
+            ),
+        })
+        .collect()
+}
+
+const STYLE: &str = "body{margin:0;background:#18181b;color:#efeff1;font:14px/1.5 sans-serif}
+header{position:sticky;top:0;padding:8px 16px;background:#0e0e10;border-bottom:1px solid #303032}
+h1{margin:0 0 8px;font-size:18px}
+input{width:100%;max-width:480px;padding:4px 8px;box-sizing:border-box}
+ol{margin:0;padding:8px 16px;list-style:none}
+li{padding:2px 0;overflow-wrap:anywhere}
+a{color:#bf94ff}
+.time{margin-right:6px;color:#adadb8;font-family:monospace;text-decoration:none}
+.badge{margin-right:3px;vertical-align:middle}
+.badge-text{margin-right:3px;padding:0 3px;border:1px solid #adadb8;border-radius:3px;font-size:11px}
+.name{font-weight:bold}
+.emote{vertical-align:middle}
+span.emote,.cheer{font-style:italic}
+.mention{font-weight:bold}
+.img{display:inline-block;vertical-align:middle;background:no-repeat center/contain}
+.img.emote{width:28px;height:28px}
+.img.badge{width:18px;height:18px}
+";
+
+const SCRIPT: &str = "const search = document.getElementById(\"search\");
+const count = document.getElementById(\"count\");
+const messages = document.querySelectorAll(\"#chat li\");
+search.addEventListener(\"input\", () => {
+  const query = search.value.trim().toLowerCase();
+  let shown = 0;
+  for (const message of messages) {
+    message.hidden = !message.dataset.search.includes(query);
+    if (!message.hidden) shown++;
+  }
+  count.textContent = shown + \" of \" + messages.length + \" messages\";
+});
+";
+
+/// Renders the chat of the VOD with ID `vod_id` as a page with a searchable
+/// list of the messages, each linking to its time in the VOD. Images that
+/// aren't in `images` are replaced with text. Comments don't need to be
+/// sorted.
+pub fn render(
+    endpoints: &Endpoints,
+    vod_id: &str,
+    comments: &[Comment],
+    badges: &ChatBadges,
+    images: &Images,
+) -> String {
+    let mut comments = comments.iter().collect::<Vec<_>>();
+    comments.sort_by(|a, b| {
+        a.content_offset_seconds
+            .partial_cmp(&b.content_offset_seconds)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    let vod_url = format!("https://www.twitch.tv/videos/{}", escape(vod_id));
+    let classes = images.classes();
+
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>Chat of VOD {id}</title>\n<style>\n{style}{images}</style>\n</head>\n<body>\n\
+         <header>\n<h1>Chat of <a href=\"{url}\">VOD {id}</a></h1>\n\
+         <input id=\"search\" type=\"search\" placeholder=\"Search messages and users\" autofocus>\n\
+         <span id=\"count\">{count} messages</span>\n</header>\n<ol id=\"chat\">\n",
+        id = escape(vod_id),
+        url = vod_url,
+        style = STYLE,
+        images = images.style(),
+        count = comments.len()
+    );
+    for comment in comments {
+        let offset = comment.offset().as_secs();
+        let (hours, minutes, seconds) = (offset / 3600, offset / 60 % 60, offset % 60);
+        let badges = comment
+            .user
+            .badges
+            .iter()
+            .map(|badge| {
+                let name = format!("{}/{}", badge.id, badge.version);
+                let fallback = format!("<span class=\"badge-text\">{}</span>", escape(&badge.id));
+                image(&classes, badges.image(badge), "badge", &name, &fallback)
+            })
+            .collect::<String>();
+        let color = comment
+            .user
+            .color
+            .as_deref()
+            .filter(|color| {
+                color.len() == 7
+                    && color.starts_with('#')
+                    && color[1..].bytes().all(|b| b.is_ascii_hexdigit())
+            })
+            .map(|color| format!(" style=\"color:{}\"", color))
+            .unwrap_or_default();
+        let search = format!("{} {}", comment.user.display_name, comment.message).to_lowercase();
+        let _ = writeln!(
+            out,
+            "<li data-search=\"{}\"><a class=\"time\" href=\"{}?t={}h{}m{}s\">{:02}:{:02}:{:02}</a>\
+             {}<span class=\"name\"{}>{}</span>: {}</li>",
+            escape(&search),
+            vod_url,
+            hours,
+            minutes,
+            seconds,
+            hours,
+            minutes,
+            seconds,
+            badges,
+            color,
+            escape(&comment.user.display_name),
+            message(endpoints, comment, &classes)
+        );
+    }
+    let _ = write!(
+        out,
+        "</ol>\n<script>\n{}</script>\n</body>\n</html>\n",
+        SCRIPT
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::test_support::comment;
+    use crate::api::CommentUserBadge;
+    use crate::mock::{MockServer, Route};
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn render_page() {
+        let text = |text: &str| Fragment::Text {
+            text: text.to_string(),
+        };
+        let kappa = Fragment::Emote {
+            text: "Kappa".to_string(),
+            id: "25".to_string(),
+            source: EmoteSource::Twitch,
+            begin: 8,
+            end: 12,
+        };
+        let badge = |id: &str, version: &str| CommentUserBadge {
+            id: id.to_string(),
+            version: version.to_string(),
+        };
+        let mut first = comment()
+            .offset(3723.5)
+            .fragments(vec![text("<b>hi</b> "), kappa])
+            .build();
+        first.user.color = Some("#FF0000\"".to_string());
+        first.user.badges = vec![badge("subscriber", "12"), badge("vip", "1")];
+        let mut second = comment().offset(5.0).build();
+        second.user.color = Some("#FF0000\"".to_string());
+        second.user.badges = vec![badge("subscriber", "12")];
+        let comments = vec![first, second];
+        let mut badges = ChatBadges::default();
+        badges.images.insert(
+            ("subscriber".to_string(), "12".to_string()),
+            "https://example.com/sub12".to_string(),
+        );
+        let endpoints = Endpoints::default();
+        let urls = image_urls(&endpoints, &comments, &badges);
+        assert_eq!(
+            urls,
+            vec![
+                "https://example.com/sub12",
+                "https://static-cdn.jtvnw.net/emoticons/v2/25/default/dark/1.0",
+            ]
+        );
+        let mut images = Images::default();
+        images.images.insert(
+            urls[0].clone(),
+            Image {
+                data: "data:image/png;base64,AA==".to_string(),
+                size: Some((18, 18)),
+            },
+        );
+
+        let page = render(&endpoints, "123", &comments, &badges, &images);
+        assert!(page.contains("<span id=\"count\">2 messages</span>"));
+        assert!(page.contains(
+            "<li data-search=\"alice &lt;b&gt;hi&lt;/b&gt; kappa\">\
+             <a class=\"time\" href=\"https://www.twitch.tv/videos/123?t=1h2m3s\">01:02:03</a>\
+             <span class=\"img badge i0\" role=\"img\" aria-label=\"subscriber/12\" title=\"subscriber/12\"></span>\
+             <span class=\"badge-text\">vip</span>\
+             <span class=\"name\">Alice</span>: &lt;b&gt;hi&lt;/b&gt; <span class=\"emote\">Kappa</span></li>\n"
+        ));
+        // Each image is embedded once, however many times it's shown.
+        assert!(page.contains(
+            ".img.i0{width:18px;height:18px;background-image:url(data:image/png;base64,AA==)}\n"
+        ));
+        assert_eq!(page.matches("base64,").count(), 1);
+        assert_eq!(page.matches("<span class=\"img badge i0\"").count(), 2);
+        // Comments are sorted by offset.
+        assert!(page.find("?t=0h0m5s").unwrap() < page.find("?t=1h2m3s").unwrap());
+    }
+
+    #[test]
+    fn embed_images() {
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let (images, errors) = runtime.block_on(async {
+            let server = MockServer::start(vec![
+                Route::new("/7tv-cdn/emote/60a/1x.webp", "webp"),
+                Route::new("/badge.gif", "gif"),
+            ])
+            .await;
+            let urls = vec![
+                emote_url(&server.endpoints(), EmoteSource::SevenTv, "60a"),
+                format!("{}/badge.gif", server.url()),
+                format!("{}/missing", server.url()),
+            ];
+            let (images, errors) = Images::fetch(&server.client(), &urls).await;
+            let images = urls
+                .iter()
+                .map(|url| images.get(url).map(str::to_string))
+                .collect::<Vec<_>>();
+            (images, errors)
+        });
+        assert_eq!(
+            images,
+            vec![
+                Some("data:image/webp;base64,d2VicA==".to_string()),
+                Some("data:image/gif;base64,Z2lm".to_string()),
+                None,
+            ]
+        );
+        assert!(matches!(errors.as_slice(), [Error::NotFound(_)]));
+    }
+
+    #[test]
+    fn image_sizes() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        png.extend_from_slice(&[0, 0, 0, 28, 0, 0, 0, 18]);
+        assert_eq!(image_size(&png), Some((28, 18)));
+        assert_eq!(image_size(b"GIF89a\x70\0\x1c\0"), Some((112, 28)));
+        let mut webp = b"RIFF\0\0\0\0WEBPVP8X".to_vec();
+        webp.extend_from_slice(&[0; 8]);
+        webp.extend_from_slice(&[55, 0, 0, 27, 0, 0]);
+        assert_eq!(image_size(&webp), Some((56, 28)));
+        assert_eq!(image_size(b"GIF89a\x70"), None);
+        assert_eq!(image_size(b"png"), None);
+    }
+}
@@ -0,0 +1,30 @@
+//! Client for the Twitch APIs used by `twitch-cli`.
+
+pub mod api;
+pub mod auth;
+pub mod chat;
+pub mod client;
+pub mod config;
+pub mod download;
+pub mod emotes;
+pub mod error;
+mod files;
+pub mod hls;
+pub mod html;
+pub mod link;
+#[cfg(test)]
+mod mock;
+pub mod record;
+pub mod stats;
+pub mod subtitles;
+pub mod time;
+pub mod watch;
+
+pub use api::{
+    ChatBadges, Chatters, ChattersChatters, Clip, ClipMedia, ClipQuality, Clips, Comment,
+    CommentUser, CommentUserBadge, Comments, EmoteSource, Fragment, LiveStatus, Segment, Stream,
+    UserData, UserQuery, Vod, Vods,
+};
+pub use client::{Credentials, HelixCredentials, TwitchClient};
+pub use config::{Config, Endpoints, WatchConfig, WatchedChannel};
+pub use error::{Error, Result};
@@ -0,0 +1,202 @@
+//! Twitch links, as pasted from a browser or from chat.
+
+use crate::error::{Error, Result};
+use crate::time;
+use reqwest::Url;
+
+/// What a Twitch link points to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Link {
+    /// A VOD, with the position given by `?t=` if any.
+    Vod {
+        id: String,
+        start: Option<f64>,
+    },
+    Channel(String),
+    Clip(String),
+}
+
+/// First path segments of `twitch.tv` links that aren't channels.
+const RESERVED: &[&str] = &[
+    "directory",
+    "downloads",
+    "drops",
+    "embed",
+    "friends",
+    "inventory",
+    "jobs",
+    "messages",
+    "p",
+    "popout",
+    "search",
+    "settings",
+    "subscriptions",
+    "turbo",
+    "videos",
+    "wallet",
+];
+
+impl Link {
+    /// Parses a link to a VOD (`twitch.tv/videos/123?t=1h2m3s`), a channel
+    /// (`twitch.tv/<login>`) or a clip (`twitch.tv/<login>/clip/<slug>`,
+    /// `clips.twitch.tv/<slug>`), on `www.` or `m.` as well and with or
+    /// without scheme. Returns `None` if `value` isn't such a link.
+    pub fn parse(value: &str) -> Option<Link> {
+        let value = value.trim();
+        let rest = value
+            .strip_prefix("https://")
+            .or_else(|| value.strip_prefix("http://"))
+            .unwrap_or(value);
+        let url = Url::parse(&format!("https://{}", rest)).ok()?;
+        let host = url.host_str()?.to_ascii_lowercase();
+        let host = host
+            .strip_prefix("www.")
+            .or_else(|| host.strip_prefix("m."))
+            .unwrap_or(&host);
+        let segments = url
+            .path_segments()?
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>();
+        let query = |key: &str| {
+            url.query_pairs()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.into_owned())
+        };
+
+        match (host, segments.as_slice()) {
+            ("clips.twitch.tv", ["embed"]) => query("clip").map(Link::Clip),
+            ("clips.twitch.tv", [slug]) => Some(Link::Clip(slug.to_string())),
+            ("twitch.tv", ["videos", id])
+            | ("twitch.tv", [_, "video", id])
+            | ("twitch.tv", [_, "v", id]) => Some(Link::Vod {
+                id: id.to_string(),
+                start: query("t").and_then(|t| time::parse_timestamp(&t)),
+            }),
+            ("twitch.tv", [_, "clip", slug]) => Some(Link::Clip(slug.to_string())),
+            ("twitch.tv", [login, ..]) if !RESERVED.contains(login) => {
+                Some(Link::Channel(login.to_string()))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn is_word(value: &str, extra: char) -> bool {
+    !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == extra)
+}
+
+/// ID of a VOD given either bare or as a link, with the start position of
+/// the link.
+pub fn vod(value: &str) -> Result<(String, Option<f64>)> {
+    match Link::parse(value) {
+        Some(Link::Vod { id, start }) if id.bytes().all(|b| b.is_ascii_digit()) => Ok((id, start)),
+        None if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+            Ok((value.to_string(), None))
+        }
+        _ => Err(Error::InvalidArgument(format!(
+            "`{}` isn't a VOD ID or link",
+            value
+        ))),
+    }
+}
+
+/// Login of a channel given either bare or as a link.
+pub fn channel(value: &str) -> Result<String> {
+    match Link::parse(value) {
+        Some(Link::Channel(login)) if is_word(&login, '_') => Ok(login),
+        None if is_word(value, '_') => Ok(value.to_string()),
+        _ => Err(Error::InvalidArgument(format!(
+            "`{}` isn't a username or channel link",
+            value
+        ))),
+    }
+}
+
+/// Slug of a clip given either bare or as a link.
+pub fn clip(value: &str) -> Result<String> {
+    match Link::parse(value) {
+        Some(Link::Clip(slug)) if is_word(&slug, '-') => Ok(slug),
+        None if is_word(value, '-') => Ok(value.to_string()),
+        _ => Err(Error::InvalidArgument(format!(
+            "`{}` isn't a clip slug or link",
+            value
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links() {
+        let vod = |id: &str, start| {
+            Some(Link::Vod {
+                id: id.to_string(),
+                start,
+            })
+        };
+        assert_eq!(
+            Link::parse("https://www.twitch.tv/videos/123?t=1h2m3s"),
+            vod("123", Some(3723.0))
+        );
+        assert_eq!(Link::parse("twitch.tv/videos/123"), vod("123", None));
+        assert_eq!(
+            Link::parse("m.twitch.tv/videos/123?filter=all&t=90s"),
+            vod("123", Some(90.0))
+        );
+        assert_eq!(
+            Link::parse("https://www.twitch.tv/dariomocciatwitch/v/123"),
+            vod("123", None)
+        );
+        assert_eq!(
+            Link::parse("https://m.twitch.tv/dariomocciatwitch"),
+            Some(Link::Channel("dariomocciatwitch".to_string()))
+        );
+        assert_eq!(
+            Link::parse("twitch.tv/dariomocciatwitch/videos?filter=archives"),
+            Some(Link::Channel("dariomocciatwitch".to_string()))
+        );
+        assert_eq!(
+            Link::parse("https://www.twitch.tv/dariomocciatwitch/clip/Funny-Slug_1?filter=clips"),
+            Some(Link::Clip("Funny-Slug_1".to_string()))
+        );
+        assert_eq!(
+            Link::parse("https://clips.twitch.tv/Funny-Slug_1"),
+            Some(Link::Clip("Funny-Slug_1".to_string()))
+        );
+        assert_eq!(
+            Link::parse("https://clips.twitch.tv/embed?clip=Funny-Slug_1&parent=example.com"),
+            Some(Link::Clip("Funny-Slug_1".to_string()))
+        );
+        assert_eq!(Link::parse("https://twitch.tv/directory"), None);
+        assert_eq!(Link::parse("https://example.com/videos/123"), None);
+        assert_eq!(Link::parse("dariomocciatwitch"), None);
+    }
+
+    #[test]
+    fn bare_values() {
+        assert_eq!(vod("123").unwrap(), ("123".to_string(), None));
+        assert_eq!(
+            vod("twitch.tv/videos/123?t=10").unwrap(),
+            ("123".to_string(), Some(10.0))
+        );
+        assert!(vod("twitch.tv/dariomocciatwitch").is_err());
+        assert!(vod("12a").is_err());
+        assert_eq!(channel("dario_moccia").unwrap(), "dario_moccia");
+        assert_eq!(
+            channel("https://www.twitch.tv/dariomocciatwitch").unwrap(),
+            "dariomocciatwitch"
+        );
+        assert!(channel("twitch.tv/videos/123").is_err());
+        assert!(channel("dario moccia").is_err());
+        assert_eq!(clip("Funny-Slug_1").unwrap(), "Funny-Slug_1");
+        assert_eq!(
+            clip("clips.twitch.tv/Funny-Slug_1").unwrap(),
+            "Funny-Slug_1"
+        );
+    }
+}
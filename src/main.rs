@@ -1,7 +1,22 @@
-mod api;
-
-use api::{Chatters, Clips, Comments, UserData, Vod, Vods};
-use clap::{App, AppSettings, Arg, SubCommand};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use futures::stream::{self, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use twitch_cli::chat::LogWriter;
+use twitch_cli::download::{self, DownloadOptions, Progress};
+use twitch_cli::emotes::ThirdPartyEmotes;
+use twitch_cli::hls::Cut;
+use twitch_cli::html::{self, Images};
+use twitch_cli::link;
+use twitch_cli::record::{self, RecordOptions, RecordProgress};
+use twitch_cli::stats::{self, ChatStats};
+use twitch_cli::subtitles::{self, SubtitleOptions};
+use twitch_cli::watch::{self, Event as WatchEvent, WatchState};
+use twitch_cli::{
+    auth, time, ChatBadges, Clip, Config, Credentials, Error, LiveStatus, Result, Segment,
+    TwitchClient, UserQuery,
+};
 
 #[tokio::main]
 async fn main() {
@@ -10,13 +25,185 @@ async fn main() {
         .author("Matteo Guarda <matteoguarda@tutanota.com>")
         .about("A CLI for twitch that does things that can't be done with the web interface")
         .setting(AppSettings::ArgRequiredElseHelp)
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .env("TWITCH_CLI_CONFIG")
+                .help("Path of the config file (defaults to twitch-cli/config.toml in the user's config directory)"),
+        )
+        .arg(
+            Arg::with_name("api-url")
+                .long("api-url")
+                .value_name("URL")
+                .env("TWITCH_CLI_API_URL")
+                .help("Base URL of the Twitch API (default https://api.twitch.tv)"),
+        )
+        .arg(
+            Arg::with_name("gql-url")
+                .long("gql-url")
+                .value_name("URL")
+                .env("TWITCH_CLI_GQL_URL")
+                .help("Base URL of the Twitch GraphQL API (default https://gql.twitch.tv)"),
+        )
+        .arg(
+            Arg::with_name("tmi-url")
+                .long("tmi-url")
+                .value_name("URL")
+                .env("TWITCH_CLI_TMI_URL")
+                .help("Base URL of the Twitch TMI API (default https://tmi.twitch.tv)"),
+        )
+        .arg(
+            Arg::with_name("usher-url")
+                .long("usher-url")
+                .value_name("URL")
+                .env("TWITCH_CLI_USHER_URL")
+                .help("Base URL of the Twitch playlists server (default https://usher.ttvnw.net)"),
+        )
+        .arg(
+            Arg::with_name("oauth-url")
+                .long("oauth-url")
+                .value_name("URL")
+                .env("TWITCH_CLI_OAUTH_URL")
+                .help("Base URL of the Twitch OAuth server (default https://id.twitch.tv/oauth2)"),
+        )
+        .arg(
+            Arg::with_name("bttv-url")
+                .long("bttv-url")
+                .value_name("URL")
+                .env("TWITCH_CLI_BTTV_URL")
+                .help("Base URL of the BetterTTV API (default https://api.betterttv.net/3)"),
+        )
+        .arg(
+            Arg::with_name("ffz-url")
+                .long("ffz-url")
+                .value_name("URL")
+                .env("TWITCH_CLI_FFZ_URL")
+                .help("Base URL of the FrankerFaceZ API (default https://api.frankerfacez.com/v1)"),
+        )
+        .arg(
+            Arg::with_name("seventv-url")
+                .long("seventv-url")
+                .value_name("URL")
+                .env("TWITCH_CLI_SEVENTV_URL")
+                .help("Base URL of the 7TV API (default https://7tv.io/v3)"),
+        )
+        .arg(
+            Arg::with_name("twitch-cdn-url")
+                .long("twitch-cdn-url")
+                .value_name("URL")
+                .env("TWITCH_CLI_TWITCH_CDN_URL")
+                .help("Base URL of the Twitch emote images (default https://static-cdn.jtvnw.net)"),
+        )
+        .arg(
+            Arg::with_name("bttv-cdn-url")
+                .long("bttv-cdn-url")
+                .value_name("URL")
+                .env("TWITCH_CLI_BTTV_CDN_URL")
+                .help("Base URL of the BetterTTV emote images (default https://cdn.betterttv.net)"),
+        )
+        .arg(
+            Arg::with_name("ffz-cdn-url")
+                .long("ffz-cdn-url")
+                .value_name("URL")
+                .env("TWITCH_CLI_FFZ_CDN_URL")
+                .help("Base URL of the FrankerFaceZ emote images (default https://cdn.frankerfacez.com)"),
+        )
+        .arg(
+            Arg::with_name("seventv-cdn-url")
+                .long("seventv-cdn-url")
+                .value_name("URL")
+                .env("TWITCH_CLI_SEVENTV_CDN_URL")
+                .help("Base URL of the 7TV emote images (default https://cdn.7tv.app)"),
+        )
+        .arg(
+            Arg::with_name("token-file")
+                .long("token-file")
+                .value_name("FILE")
+                .env("TWITCH_CLI_TOKEN_FILE")
+                .help("Path of the file where the OAuth token is stored (defaults to twitch-cli/token.json in the user's config directory)"),
+        )
+        .subcommand(
+            SubCommand::with_name("auth")
+                .about("Gets an OAuth token for the Twitch API and stores it")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("client-credentials")
+                        .about("Gets an app access token using the client ID and secret of a Twitch application")
+                        .arg(
+                            Arg::with_name("client-id")
+                                .long("client-id")
+                                .value_name("CLIENT_ID")
+                                .env("TWITCH_CLI_CLIENT_ID")
+                                .required(true)
+                                .help("Client ID of the Twitch application"),
+                        )
+                        .arg(
+                            Arg::with_name("client-secret")
+                                .long("client-secret")
+                                .value_name("CLIENT_SECRET")
+                                .env("TWITCH_CLI_CLIENT_SECRET")
+                                .required(true)
+                                .help("Client secret of the Twitch application"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("device")
+                        .about("Gets a user access token by authorizing this device from a browser")
+                        .arg(
+                            Arg::with_name("client-id")
+                                .long("client-id")
+                                .value_name("CLIENT_ID")
+                                .env("TWITCH_CLI_CLIENT_ID")
+                                .required(true)
+                                .help("Client ID of the Twitch application"),
+                        )
+                        .arg(
+                            Arg::with_name("scopes")
+                                .long("scopes")
+                                .value_name("SCOPE")
+                                .multiple(true)
+                                .help("Scopes to request"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("status")
+                        .about("Validates the stored token, refreshing it if needed"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("infos")
-                .about("Shows infos about an user")
+                .about("Shows infos about users, one JSON object per line")
+                .after_help(
+                    "Users that don't exist are reported on stderr, and the command then exits \
+                     with 5.",
+                )
                 .arg(
                     Arg::with_name("USERNAME")
-                        .required(true)
-                        .help("Username of the user to show infos about"),
+                        .multiple(true)
+                        .required_unless_one(&["id", "file", "id-file"])
+                        .help("Username of the user to show infos about, or a link to their channel"),
+                )
+                .arg(
+                    Arg::with_name("id")
+                        .long("id")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("ID of a user to show infos about"),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .short("f")
+                        .long("file")
+                        .takes_value(true)
+                        .help("File with a username per line, `-` for stdin"),
+                )
+                .arg(
+                    Arg::with_name("id-file")
+                        .long("id-file")
+                        .takes_value(true)
+                        .help("File with a user ID per line, `-` for stdin"),
                 ),
         )
         .subcommand(
@@ -25,7 +212,38 @@ async fn main() {
                 .arg(
                     Arg::with_name("VOD_ID")
                         .required(true)
-                        .help("ID of the VOD"),
+                        .help("ID of the VOD, or a link to it"),
+                )
+                .arg(quality_arg().help(
+                    "Rendition to link to instead of the master playlist (see the help of m3u8-gen)",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("stream-url")
+                .about("Logs the direct link to the live stream of a channel")
+                .arg(
+                    Arg::with_name("USERNAME")
+                        .required(true)
+                        .help("Username of the channel, or a link to it"),
+                )
+                .arg(quality_arg().help(
+                    "Rendition to link to instead of the master playlist (see the help of m3u8-gen)",
+                ))
+                .arg(
+                    Arg::with_name("list")
+                        .short("l")
+                        .long("list")
+                        .conflicts_with("quality")
+                        .help("Lists the available renditions instead"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("qualities")
+                .about("Lists the renditions available for a VOD")
+                .arg(
+                    Arg::with_name("VOD_ID")
+                        .required(true)
+                        .help("ID of the VOD, or a link to it"),
                 ),
         )
         .subcommand(
@@ -34,20 +252,101 @@ async fn main() {
                 .arg(
                     Arg::with_name("VOD_ID")
                         .required(true)
-                        .help("ID of the VOD"),
+                        .help("ID of the VOD, or a link to it"),
+                )
+                .arg(quality_arg().default_value("best"))
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .value_name("OUTPUT")
+                        .help("Writes the playlist to OUTPUT instead of stdout, and the exact cut points to OUTPUT.cut.json"),
+                )
+                .arg(
+                    Arg::with_name("start")
+                        .short("s")
+                        .value_name("START")
+                        .help("Start of the range, in seconds, HH:MM:SS or 1h2m3s (defaults to the ?t= of a VOD link)"),
+                )
+                .arg(
+                    Arg::with_name("end")
+                        .short("e")
+                        .value_name("END")
+                        .help("End of the range, in seconds, HH:MM:SS or 1h2m3s"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("download")
+                .about("Downloads an entire Twitch VOD or a part of it")
+                .arg(
+                    Arg::with_name("VOD_ID")
+                        .required(true)
+                        .help("ID of the VOD, or a link to it"),
+                )
+                .arg(quality_arg().default_value("best"))
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .value_name("OUTPUT")
+                        .help("Output file, remuxed with ffmpeg if it ends in .mp4 (defaults to VOD_ID.ts)"),
                 )
                 .arg(
                     Arg::with_name("start")
                         .short("s")
                         .value_name("START")
-                        .help("Start duration in seconds")
-                        .default_value("0.0"),
+                        .help("Start of the range, in seconds, HH:MM:SS or 1h2m3s (defaults to the ?t= of a VOD link)"),
                 )
                 .arg(
                     Arg::with_name("end")
                         .short("e")
                         .value_name("END")
-                        .help("End duration in seconds"),
+                        .help("End of the range, in seconds, HH:MM:SS or 1h2m3s"),
+                )
+                .arg(
+                    Arg::with_name("workers")
+                        .short("j")
+                        .value_name("WORKERS")
+                        .help("Number of segments to download at the same time")
+                        .default_value("8"),
+                )
+                .arg(
+                    Arg::with_name("retries")
+                        .long("retries")
+                        .value_name("RETRIES")
+                        .help("Number of retries for each segment")
+                        .default_value("5"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("record")
+                .about("Records the live stream of a channel until it ends")
+                .arg(
+                    Arg::with_name("USERNAME")
+                        .required(true)
+                        .help("Username of the channel, or a link to it"),
+                )
+                .arg(quality_arg().default_value("best"))
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .value_name("OUTPUT")
+                        .help("Output file, appended to if it exists (defaults to USERNAME-TIMESTAMP.ts)"),
+                )
+                .arg(
+                    Arg::with_name("retries")
+                        .long("retries")
+                        .value_name("RETRIES")
+                        .help("Number of retries for the playlist and each segment")
+                        .default_value("5"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("watch")
+                .about("Records the channels listed in the [watch] section of the config file whenever they go live")
+                .arg(
+                    Arg::with_name("state-file")
+                        .long("state-file")
+                        .value_name("FILE")
+                        .help("Where the recorded broadcasts are tracked, overrides watch.state_file"),
                 ),
         )
         .subcommand(
@@ -56,7 +355,7 @@ async fn main() {
                 .arg(
                     Arg::with_name("USERNAME")
                         .required(true)
-                        .help("Username of the user who posted the vods"),
+                        .help("Username of the user who posted the vods, or a link to their channel"),
                 )
                 .arg(
                     Arg::with_name("iterations")
@@ -74,20 +373,31 @@ async fn main() {
         .subcommand(
             SubCommand::with_name("clips")
                 .about("Shows all the clips of an user between a range of time")
+                .after_help(
+                    "Prints every clip of the range as a single JSON array, splitting ranges with \
+                     too many clips for Helix in smaller ones. With -i or -c, prints instead the \
+                     pages of clips returned by Helix, one JSON array per line.",
+                )
                 .arg(
                     Arg::with_name("USERNAME")
                         .required(true)
-                        .help("Username of the user who posted the clips"),
+                        .help("Username of the user who posted the clips, or a link to their channel"),
                 )
                 .arg(
                     Arg::with_name("STARTED_AT")
-                        .required(true)
+                        .required_unless("last")
                         .help("Start of the range (RFC3339 format) (e.g. 2020-03-26T00:00:00Z)"),
                 )
                 .arg(
                     Arg::with_name("ENDED_AT")
-                        .required(true)
-                        .help("End of the range (RFC3339 format) (e.g. 2020-04-26T00:00:00Z)"),
+                        .help("End of the range (RFC3339 format) (e.g. 2020-04-26T00:00:00Z), defaults to now"),
+                )
+                .arg(
+                    Arg::with_name("last")
+                        .long("last")
+                        .value_name("PERIOD")
+                        .conflicts_with_all(&["STARTED_AT", "ENDED_AT"])
+                        .help("Range ending now, e.g. 30d, 2w or 12h"),
                 )
                 .arg(
                     Arg::with_name("iterations")
@@ -102,13 +412,65 @@ async fn main() {
                         .help("Cursor, used to start fetching from a certain point on"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("clip-dl")
+                .about("Downloads clips, given by slug or link or as the output of `clips`")
+                .after_help(
+                    "Clips whose output already exists are skipped. The path of every clip \
+                     downloaded is printed on stdout.",
+                )
+                .arg(
+                    Arg::with_name("CLIP")
+                        .multiple(true)
+                        .required_unless("input")
+                        .help("Slug of the clip, or a link to it"),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .short("i")
+                        .long("input")
+                        .value_name("FILE")
+                        .help("Downloads every clip listed in FILE, as printed by `clips` (`-` for stdin)"),
+                )
+                .arg(
+                    Arg::with_name("quality")
+                        .short("q")
+                        .long("quality")
+                        .value_name("QUALITY")
+                        .default_value("best")
+                        .help("Rendition to use: comma separated list of qualities tried in order, each one of best, worst or the start of a rendition name (e.g. 720p60, 480)"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("TEMPLATE")
+                        .default_value("{id}.mp4")
+                        .help("Output file, where {id}, {broadcaster}, {title}, {created_at} and {quality} are replaced with those of the clip"),
+                )
+                .arg(
+                    Arg::with_name("workers")
+                        .short("j")
+                        .long("workers")
+                        .value_name("WORKERS")
+                        .help("Number of clips to download at the same time")
+                        .default_value("4"),
+                )
+                .arg(
+                    Arg::with_name("retries")
+                        .long("retries")
+                        .value_name("RETRIES")
+                        .help("Number of retries for each clip")
+                        .default_value("5"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("comments")
                 .about("Shows all the comments of a VOD")
                 .arg(
                     Arg::with_name("VOD_ID")
                         .required(true)
-                        .help("ID of the VOD"),
+                        .help("ID of the VOD, or a link to it"),
                 )
                 .arg(
                     Arg::with_name("iterations")
@@ -121,6 +483,114 @@ async fn main() {
                         .short("c")
                         .value_name("CURSOR")
                         .help("Cursor, used to start fetching from a certain point on"),
+                )
+                .arg(
+                    Arg::with_name("parallel")
+                        .short("j")
+                        .long("parallel")
+                        .value_name("RANGES")
+                        .default_value("4")
+                        .help("Number of ranges of the VOD whose comments are fetched at once, unless ITERATIONS or CURSOR are given"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .short("f")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .possible_values(&["json", "log", "srt", "vtt", "ass", "html"])
+                        .default_value("json")
+                        .help("Prints the pages of comments as JSON, the chat as plain text, or the whole chat as a subtitle track or an HTML page"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .value_name("OUTPUT")
+                        .help("Writes the log, the subtitles or the page to OUTPUT instead of stdout"),
+                )
+                .arg(
+                    Arg::with_name("wallclock")
+                        .long("wallclock")
+                        .help("Timestamps the log with the UTC date and time of the messages instead of their offset in the VOD"),
+                )
+                .arg(
+                    Arg::with_name("split-hourly")
+                        .long("split-hourly")
+                        .requires("output")
+                        .help("Writes the log of every hour of the VOD to its own file, e.g. OUTPUT.02.log for the third one"),
+                )
+                .arg(
+                    Arg::with_name("duration")
+                        .long("duration")
+                        .value_name("SECONDS")
+                        .default_value("5")
+                        .help("Seconds every message stays on screen, for subtitles"),
+                )
+                .arg(
+                    Arg::with_name("max-lines")
+                        .long("max-lines")
+                        .value_name("LINES")
+                        .default_value("5")
+                        .help("Most messages on screen at once, for subtitles"),
+                )
+                .arg(
+                    Arg::with_name("emotes")
+                        .long("emotes")
+                        .help("Recognizes the BetterTTV, FrankerFaceZ and 7TV emotes of the channel in the messages"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("chat-stats")
+                .about("Shows statistics of the chat of VODs")
+                .after_help(
+                    "Chatters are first-time chatters in the first of the VODs they chat in, \
+                     so a season of VODs should be given oldest first. The statistics of every \
+                     VOD are printed as a line of JSON or as a CSV row.",
+                )
+                .arg(
+                    Arg::with_name("VOD_ID")
+                        .multiple(true)
+                        .required_unless("input")
+                        .help("ID of the VOD, or a link to it"),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .short("i")
+                        .long("input")
+                        .value_name("FILE")
+                        .help("Also reads VOD IDs or links from FILE, one per line (`-` for stdin)"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .short("f")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .possible_values(&["json", "csv"])
+                        .default_value("json"),
+                )
+                .arg(
+                    Arg::with_name("per-minute")
+                        .long("per-minute")
+                        .help("With CSV, prints a row with the messages of every minute of the VODs instead of a summary"),
+                )
+                .arg(
+                    Arg::with_name("top")
+                        .long("top")
+                        .value_name("COUNT")
+                        .default_value("10")
+                        .help("Number of top chatters, emotes and words listed"),
+                )
+                .arg(
+                    Arg::with_name("emotes")
+                        .long("emotes")
+                        .help("Counts the BetterTTV, FrankerFaceZ and 7TV emotes of the channel as well"),
+                )
+                .arg(
+                    Arg::with_name("parallel")
+                        .short("j")
+                        .long("parallel")
+                        .value_name("RANGES")
+                        .default_value("4")
+                        .help("Number of ranges of a VOD whose comments are fetched at once"),
                 ),
         )
         .subcommand(
@@ -129,7 +599,7 @@ async fn main() {
                 .arg(
                     Arg::with_name("STREAMER_USERNAME")
                         .required(true)
-                        .help("Username of the streamer"),
+                        .help("Username of the streamer, or a link to their channel"),
                 )
                 .arg(
                     Arg::with_name("USERNAME")
@@ -140,151 +610,853 @@ async fn main() {
         .subcommand(
             SubCommand::with_name("are-live")
                 .about("Given a list of streamers, check if they are live")
+                .after_help(
+                    "Exits with 0 if every streamer is live, 12 if only some are and 13 if none \
+                     is. Streamers that can't be checked count as offline, unless none can be \
+                     checked.",
+                )
                 .arg(
                     Arg::with_name("USERNAME")
                         .required(true)
                         .multiple(true)
-                        .help("Username of the streamer"),
+                        .help("Username of the streamer, or a link to their channel"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Prints a JSON object from username to `live`, `offline` or {\"error\": ...}"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("streams")
+                .about("Shows the live streams of a list of streamers, offline ones are left out")
+                .arg(
+                    Arg::with_name("USERNAME")
+                        .required(true)
+                        .multiple(true)
+                        .help("Username of the streamer, or a link to their channel"),
                 ),
         )
         .get_matches();
 
+    if let Err(err) = run(&matches).await {
+        eprintln!("error: {}", err);
+        std::process::exit(err.exit_code());
+    }
+}
+
+async fn run(matches: &ArgMatches<'_>) -> Result<()> {
+    let config = Config::load(matches.value_of("config").map(Path::new))?;
+    let endpoints = config
+        .endpoints
+        .with_overrides(|name| matches.value_of(format!("{}-url", name)));
+    let mut client = TwitchClient::new(endpoints, Credentials::default())?;
+    let token_path = matches
+        .value_of("token-file")
+        .map(PathBuf::from)
+        .or_else(auth::Token::default_path);
+
+    if let Some(matches) = matches.subcommand_matches("auth") {
+        let token_path = token_path.ok_or_else(|| {
+            Error::Config("can't find the user's config directory, use --token-file".into())
+        })?;
+        let token = if let Some(matches) = matches.subcommand_matches("client-credentials") {
+            auth::client_credentials(
+                &client,
+                matches.value_of("client-id").unwrap(),
+                matches.value_of("client-secret").unwrap(),
+            )
+            .await?
+        } else if let Some(matches) = matches.subcommand_matches("device") {
+            let client_id = matches.value_of("client-id").unwrap();
+            let scopes = matches
+                .values_of("scopes")
+                .map(|values| values.collect::<Vec<_>>())
+                .unwrap_or_default();
+            let code = auth::device_code(&client, client_id, &scopes).await?;
+            eprintln!(
+                "Go to {} and enter the code {}",
+                code.verification_uri, code.user_code
+            );
+            auth::device_token(&client, client_id, &scopes, &code).await?
+        } else {
+            auth::load_token(&client, &token_path).await?.ok_or_else(|| {
+                Error::Auth("there is no stored token, run `twitch-cli auth client-credentials` or `twitch-cli auth device`".into())
+            })?
+        };
+
+        token.save(&token_path)?;
+        println!(
+            "{}",
+            serde_json::json!({
+                "client_id": token.client_id,
+                "expires_at": token.expires_at,
+                "scopes": token.scopes,
+            })
+        );
+        return Ok(());
+    }
+
+    if let Some(token_path) = token_path {
+        match auth::load_token(&client, &token_path).await {
+            Ok(Some(token)) => client.set_helix_credentials(token.helix_credentials()),
+            Ok(None) => {}
+            Err(err) => eprintln!("warning: can't use the stored OAuth token: {}", err),
+        }
+    }
+
     if let Some(matches) = matches.subcommand_matches("infos") {
-        let username = matches.value_of("USERNAME").unwrap();
-        let user_data = UserData::new(username).await.ok();
-        println!("{}", serde_json::to_string(&user_data).unwrap());
-        return;
+        let mut queries = matches
+            .values_of("USERNAME")
+            .into_iter()
+            .flatten()
+            .map(|login| link::channel(login).map(UserQuery::Login))
+            .chain(
+                matches
+                    .values_of("id")
+                    .into_iter()
+                    .flatten()
+                    .map(|id| Ok(UserQuery::Id(id.to_string()))),
+            )
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(path) = matches.value_of("file") {
+            for login in read_lines(path)? {
+                queries.push(UserQuery::Login(link::channel(&login)?));
+            }
+        }
+        if let Some(path) = matches.value_of("id-file") {
+            queries.extend(read_lines(path)?.into_iter().map(UserQuery::Id));
+        }
+        let mut seen = HashSet::new();
+        queries.retain(|query| seen.insert(query.clone()));
+
+        let (users, unknown) = client.users(&queries).await?;
+        for user in &users {
+            println!("{}", serde_json::to_string(user)?);
+        }
+        for query in &unknown {
+            eprintln!("error: no Twitch user with {}", query);
+        }
+        if let Some(UserQuery::Login(login) | UserQuery::Id(login)) = unknown.first() {
+            std::process::exit(Error::UnknownUser(login.clone()).exit_code());
+        }
+        return Ok(());
     }
 
     if let Some(matches) = matches.subcommand_matches("dl") {
-        let vod_id = matches.value_of("VOD_ID").unwrap();
-        if let Ok(direct_link) = Vod::fetch_direct_link(&vod_id).await {
-            println!("{}", direct_link);
+        let vod_id = &link::vod(matches.value_of("VOD_ID").unwrap())?.0;
+        match matches.value_of("quality") {
+            Some(quality) => println!("{}", client.variant(vod_id, quality).await?.uri),
+            None => println!("{}", client.fetch_direct_link(vod_id).await?),
+        }
+        return Ok(());
+    }
+
+    if let Some(matches) = matches.subcommand_matches("stream-url") {
+        let username = &link::channel(matches.value_of("USERNAME").unwrap())?;
+        if matches.is_present("list") {
+            let master = client.live_master_playlist(username).await?;
+            println!("{}", serde_json::to_string(&master.variants)?);
+        } else if let Some(quality) = matches.value_of("quality") {
+            println!("{}", client.live_variant(username, quality).await?.uri);
+        } else {
+            println!("{}", client.fetch_live_link(username).await?);
         }
-        return;
+        return Ok(());
+    }
+
+    if let Some(matches) = matches.subcommand_matches("qualities") {
+        let vod_id = &link::vod(matches.value_of("VOD_ID").unwrap())?.0;
+        let master = client.master_playlist(vod_id).await?;
+        println!("{}", serde_json::to_string(&master.variants)?);
+        return Ok(());
+    }
+
+    if let Some(matches) = matches.subcommand_matches("streams") {
+        let usernames = matches
+            .values_of("USERNAME")
+            .unwrap()
+            .map(link::channel)
+            .collect::<Result<Vec<_>>>()?;
+        let usernames = usernames.iter().map(String::as_str).collect::<Vec<_>>();
+        let streams = client
+            .streams(&usernames)
+            .await?
+            .into_iter()
+            .map(|stream| {
+                let uptime = stream.uptime();
+                let mut value = serde_json::to_value(stream)?;
+                value["uptime"] = serde_json::json!(uptime.map(format_duration));
+                value["uptime_seconds"] = serde_json::json!(uptime.map(|x| x.as_secs()));
+                Ok(value)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        println!("{}", serde_json::to_string(&streams)?);
+        return Ok(());
     }
 
     if let Some(matches) = matches.subcommand_matches("are-live") {
         let usernames = matches
             .values_of("USERNAME")
             .unwrap()
-            .collect::<Vec<&str>>();
-        let are_live = UserData::are_live(&usernames).await;
-        for (idx, is_live) in are_live.into_iter().enumerate() {
-            println!("{}: {}", usernames[idx], is_live);
+            .map(link::channel)
+            .collect::<Result<Vec<_>>>()?;
+        let usernames = usernames.iter().map(String::as_str).collect::<Vec<_>>();
+        let statuses = client.are_live(&usernames).await;
+
+        if matches.is_present("json") {
+            let json = statuses
+                .iter()
+                .map(|(username, status)| {
+                    let status = match status {
+                        LiveStatus::Live(_) => serde_json::json!("live"),
+                        LiveStatus::Offline => serde_json::json!("offline"),
+                        LiveStatus::Error(err) => serde_json::json!({ "error": err.to_string() }),
+                    };
+                    (username.clone(), status)
+                })
+                .collect::<serde_json::Map<_, _>>();
+            println!("{}", serde_json::to_string(&json)?);
+        } else {
+            for username in &usernames {
+                match &statuses[*username] {
+                    LiveStatus::Error(err) => eprintln!("{}: error: {}", username, err),
+                    status => println!("{}: {}", username, status.is_live()),
+                }
+            }
+        }
+
+        let live = statuses.values().filter(|status| status.is_live()).count();
+        let first_error = statuses.values().find_map(|status| match status {
+            LiveStatus::Error(err) => Some(err),
+            _ => None,
+        });
+        match first_error {
+            Some(err) if statuses.values().all(|s| matches!(s, LiveStatus::Error(_))) => {
+                std::process::exit(err.exit_code())
+            }
+            _ if live == statuses.len() => return Ok(()),
+            _ if live > 0 => std::process::exit(12),
+            _ => std::process::exit(13),
         }
-        return;
     }
 
     if let Some(matches) = matches.subcommand_matches("chatters") {
-        let streamer = matches.value_of("STREAMER_USERNAME").unwrap();
+        let streamer = &link::channel(matches.value_of("STREAMER_USERNAME").unwrap())?;
         let usernames = matches
             .values_of("USERNAME")
             .map(|values| values.collect::<Vec<_>>())
-            .unwrap_or(Vec::new());
+            .unwrap_or_default();
 
-        if let Ok(chatters) = Chatters::new(streamer).await {
-            if usernames.len() == 0 {
-                println!("{}", serde_json::to_string(&chatters).unwrap());
-                return;
-            }
-            for (idx, is_online) in chatters.are_online(&usernames).into_iter().enumerate() {
-                println!("{}: {}", usernames[idx], is_online);
-            }
-        } else {
-            eprintln!("There is not a Twitch user named `{}`", streamer);
-            std::process::exit(1);
+        let chatters = client.chatters(streamer).await?;
+        if usernames.is_empty() {
+            println!("{}", serde_json::to_string(&chatters)?);
+            return Ok(());
         }
+        for (idx, is_online) in chatters.are_online(&usernames).into_iter().enumerate() {
+            println!("{}: {}", usernames[idx], is_online);
+        }
+        return Ok(());
     }
 
     if let Some(matches) = matches.subcommand_matches("m3u8-gen") {
-        let vod_id = matches.value_of("VOD_ID").unwrap();
-        let start = matches
-            .value_of("start")
-            .map(|x| x.parse().expect("start parameter is not a valid float"));
-        let end = matches
-            .value_of("end")
-            .map(|x| x.parse().expect("end parameter is not a valid float"));
-
-        if let Ok(m3u8) = Vod::m3u8_gen(&vod_id, start, end).await {
-            println!("{}", m3u8);
+        let (vod_id, link_start) = link::vod(matches.value_of("VOD_ID").unwrap())?;
+        let vod_id = &vod_id;
+        let (start, end) = range(matches, link_start)?;
+
+        let quality = matches.value_of("quality").unwrap();
+
+        let (mut playlist, cut) = client.trimmed_playlist(vod_id, quality, start, end).await?;
+        playlist.end_list = true;
+        match matches.value_of("output").map(Path::new) {
+            Some(output) => {
+                std::fs::write(output, playlist.to_string())?;
+                cut.save(&Cut::path(output))?;
+            }
+            None => print!("{}", playlist),
+        }
+        return Ok(());
+    }
+
+    if let Some(matches) = matches.subcommand_matches("download") {
+        let (vod_id, link_start) = link::vod(matches.value_of("VOD_ID").unwrap())?;
+        let vod_id = &vod_id;
+        let output = matches
+            .value_of("output")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(format!("{}.ts", vod_id)));
+        let (start, end) = range(matches, link_start)?;
+        let options = DownloadOptions {
+            workers: parse_number("workers", matches.value_of("workers").unwrap())?,
+            retries: parse_number("retries", matches.value_of("retries").unwrap())?,
+        };
+
+        let quality = matches.value_of("quality").unwrap();
+
+        let (playlist, cut) = client.trimmed_playlist(vod_id, quality, start, end).await?;
+        let segments = Segment::from_playlist(&playlist);
+        download::download(
+            &client,
+            vod_id,
+            quality,
+            &segments,
+            &output,
+            &options,
+            print_progress,
+        )
+        .await?;
+        eprintln!();
+        if start != Some(0.0) || end.is_some() {
+            cut.save(&Cut::path(&output))?;
+        }
+        return Ok(());
+    }
+
+    if let Some(matches) = matches.subcommand_matches("record") {
+        let username = &link::channel(matches.value_of("USERNAME").unwrap())?;
+        let output = matches
+            .value_of("output")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(format!("{}-{}.ts", username, time::now())));
+        let options = RecordOptions {
+            retries: parse_number("retries", matches.value_of("retries").unwrap())?,
+            ..RecordOptions::default()
+        };
+
+        let quality = matches.value_of("quality").unwrap();
+
+        record::record(
+            &client,
+            username,
+            quality,
+            &output,
+            &options,
+            print_record_progress,
+        )
+        .await?;
+        eprintln!();
+        return Ok(());
+    }
+
+    if let Some(matches) = matches.subcommand_matches("watch") {
+        let watch_config = &config.watch;
+        if watch_config.channels.is_empty() {
+            return Err(Error::InvalidArgument(
+                "no channels to watch, add them as [[watch.channels]] to the config file".into(),
+            ));
         }
-        return;
+        let state_path = matches
+            .value_of("state-file")
+            .map(PathBuf::from)
+            .or_else(|| watch_config.state_file.clone())
+            .or_else(WatchState::default_path)
+            .ok_or_else(|| {
+                Error::InvalidArgument("can't find a data directory, pass --state-file".into())
+            })?;
+
+        let (stop_tx, stop) = tokio::sync::watch::channel(false);
+        tokio::spawn(async move {
+            shutdown_signal().await;
+            eprintln!("stopping the recordings...");
+            let _ = stop_tx.broadcast(true);
+        });
+        watch::watch(
+            &client,
+            &config.watch,
+            &state_path,
+            stop,
+            |event| match event {
+                WatchEvent::Started {
+                    channel,
+                    output,
+                    resumed,
+                } => eprintln!(
+                    "{}: {} recording to {}",
+                    channel,
+                    if resumed { "resumed" } else { "started" },
+                    output.display()
+                ),
+                WatchEvent::Finished { channel, progress } => eprintln!(
+                    "{}: {} recording after {} segments ({} missed)",
+                    channel,
+                    if progress.interrupted {
+                        "interrupted"
+                    } else {
+                        "finished"
+                    },
+                    progress.segments,
+                    progress.missed
+                ),
+                WatchEvent::Failed { channel, error } => eprintln!("{}: error: {}", channel, error),
+            },
+        )
+        .await?;
+        return Ok(());
     }
 
     if let Some(matches) = matches.subcommand_matches("vods") {
-        let username = matches.value_of("USERNAME").unwrap();
+        let username = &link::channel(matches.value_of("USERNAME").unwrap())?;
         let iterations = matches
             .value_of("iterations")
             .and_then(|x| x.parse().ok())
-            .unwrap_or(std::u64::MAX);
+            .unwrap_or(u64::MAX);
         let mut cursor = matches.value_of("cursor").map(|x| x.to_string());
 
         let mut i = 0;
         loop {
-            if let Ok(vods) = Vods::new(&username, cursor.as_deref()).await {
-                println!("{}", serde_json::to_string(&vods.vods).unwrap());
-                cursor = vods.cursor.clone();
-                i += 1;
-            } else {
-                break;
-            }
+            let vods = client.vods(username, cursor.as_deref()).await?;
+            println!("{}", serde_json::to_string(&vods.vods)?);
+            cursor = vods.cursor;
+            i += 1;
 
             if cursor.is_none() || i == iterations {
                 break;
             }
         }
-        return;
+        return Ok(());
     }
 
     if let Some(matches) = matches.subcommand_matches("clips") {
-        let username = matches.value_of("USERNAME").unwrap();
-        let start = matches.value_of("STARTED_AT").unwrap();
-        let end = matches.value_of("ENDED_AT").unwrap();
+        let username = &link::channel(matches.value_of("USERNAME").unwrap())?;
+        let now = time::now() as i64;
+        let (start, end) = match matches.value_of("last") {
+            Some(last) => {
+                let period = time::parse_period(last).ok_or_else(|| {
+                    Error::InvalidArgument(format!(
+                        "last parameter `{}` is not a valid period (e.g. 30d, 2w or 12h)",
+                        last
+                    ))
+                })?;
+                (now - period as i64, now)
+            }
+            None => (
+                parse_date("STARTED_AT", matches.value_of("STARTED_AT").unwrap())?,
+                matches
+                    .value_of("ENDED_AT")
+                    .map(|x| parse_date("ENDED_AT", x))
+                    .transpose()?
+                    .unwrap_or(now),
+            ),
+        };
+        if start >= end {
+            return Err(Error::InvalidArgument(
+                "the start of the range must come before its end".into(),
+            ));
+        }
+
+        if !matches.is_present("iterations") && !matches.is_present("cursor") {
+            let clips = client.all_clips(username, start, end).await?;
+            println!("{}", serde_json::to_string(&clips)?);
+            return Ok(());
+        }
+
+        let (start, end) = (&time::format_rfc3339(start), &time::format_rfc3339(end));
         let iterations = matches
             .value_of("iterations")
             .and_then(|x| x.parse().ok())
-            .unwrap_or(std::u64::MAX);
+            .unwrap_or(u64::MAX);
         let mut cursor = matches.value_of("cursor").map(|x| x.to_string());
 
         let mut i = 0;
         loop {
-            if let Ok(clips) = Clips::new(&username, &start, &end, cursor.as_deref()).await {
-                println!("{}", serde_json::to_string(&clips.clips).unwrap());
-                cursor = clips.cursor.clone();
-                i += 1;
-            } else {
-                break;
-            }
+            let clips = client
+                .clips(username, start, end, cursor.as_deref())
+                .await?;
+            println!("{}", serde_json::to_string(&clips.clips)?);
+            cursor = clips.cursor;
+            i += 1;
 
             if cursor.is_none() || i == iterations {
                 break;
             }
         }
-        return;
+        return Ok(());
+    }
+
+    if let Some(matches) = matches.subcommand_matches("clip-dl") {
+        let template = matches.value_of("output").unwrap();
+        let quality = matches.value_of("quality").unwrap();
+        let retries = parse_number("retries", matches.value_of("retries").unwrap())?;
+        let workers: usize = parse_number("workers", matches.value_of("workers").unwrap())?;
+
+        // Only Helix knows the metadata of a clip, and it needs a token.
+        let needs_metadata = ["{broadcaster}", "{title}", "{created_at}"]
+            .iter()
+            .any(|key| template.contains(key));
+        let mut clips = vec![];
+        for value in matches.values_of("CLIP").into_iter().flatten() {
+            let slug = link::clip(value)?;
+            clips.push(if needs_metadata {
+                client.clip(&slug).await?
+            } else {
+                Clip {
+                    id: slug,
+                    ..Clip::default()
+                }
+            });
+        }
+        if let Some(path) = matches.value_of("input") {
+            let content = read_lines(path)?.join("\n");
+            for page in serde_json::Deserializer::from_str(&content).into_iter::<Vec<Clip>>() {
+                clips.extend(page?);
+            }
+        }
+
+        let client = &client;
+        let mut downloads = stream::iter(&clips)
+            .map(|clip| async move {
+                let result = download_clip(client, clip, quality, template, retries).await;
+                (clip, result)
+            })
+            .buffer_unordered(workers.max(1));
+        // A clip failing doesn't stop the others, the first error sets the
+        // exit code.
+        let mut failed = None;
+        let mut failures = 0;
+        while let Some((clip, result)) = downloads.next().await {
+            match result {
+                Ok(Some(output)) => println!("{}", output.display()),
+                Ok(None) => eprintln!("{}: already downloaded, skipping", clip.id),
+                Err(err) => {
+                    eprintln!("{}: error: {}", clip.id, err);
+                    failures += 1;
+                    failed.get_or_insert(err);
+                }
+            }
+        }
+        if failures > 0 && clips.len() > 1 {
+            eprintln!("{} of {} clips failed", failures, clips.len());
+        }
+        return match failed {
+            Some(err) if clips.len() == 1 => Err(err),
+            Some(err) => std::process::exit(err.exit_code()),
+            None => Ok(()),
+        };
+    }
+
+    if let Some(matches) = matches.subcommand_matches("chat-stats") {
+        let mut vod_ids = matches
+            .values_of("VOD_ID")
+            .into_iter()
+            .flatten()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        if let Some(path) = matches.value_of("input") {
+            vod_ids.extend(read_lines(path)?);
+        }
+        let vod_ids = vod_ids
+            .iter()
+            .map(|vod_id| Ok(link::vod(vod_id)?.0))
+            .collect::<Result<Vec<_>>>()?;
+        let csv = matches.value_of("format") == Some("csv");
+        let per_minute = matches.is_present("per-minute");
+        let top = parse_number("top", matches.value_of("top").unwrap())?;
+        let parallel = parse_number("parallel", matches.value_of("parallel").unwrap())?;
+
+        if csv {
+            if per_minute {
+                println!("{}", stats::CSV_MINUTES_HEADER);
+            } else {
+                println!("{}", stats::CSV_HEADER);
+            }
+        }
+        let mut emotes = HashMap::new();
+        let mut seen = HashSet::new();
+        for vod_id in &vod_ids {
+            let mut ranges = client.all_comments(vod_id, parallel).await?.boxed_local();
+            let mut comments = vec![];
+            while let Some(range) = ranges.next().await {
+                comments.extend(range?);
+            }
+            if let (true, Some(comment)) = (matches.is_present("emotes"), comments.first()) {
+                let channel_id = comment.channel_id.clone();
+                if !emotes.contains_key(&channel_id) {
+                    let (fetched, errors) = ThirdPartyEmotes::new(&client, &channel_id).await;
+                    for err in errors {
+                        eprintln!("warning: can't fetch third-party emotes: {}", err);
+                    }
+                    emotes.insert(channel_id.clone(), fetched);
+                }
+                for comment in &mut comments {
+                    emotes[&channel_id].apply(comment);
+                }
+            }
+
+            let stats = ChatStats::new(vod_id, &comments, &mut seen, top);
+            match (csv, per_minute) {
+                (false, _) => println!("{}", serde_json::to_string(&stats)?),
+                (true, false) => println!("{}", stats.csv_row()),
+                (true, true) => {
+                    for row in stats.csv_minutes() {
+                        println!("{}", row);
+                    }
+                }
+            }
+        }
+        return Ok(());
     }
 
     if let Some(matches) = matches.subcommand_matches("comments") {
-        let vod_id = matches.value_of("VOD_ID").unwrap();
+        let vod_id = &link::vod(matches.value_of("VOD_ID").unwrap())?.0;
         let iterations = matches
             .value_of("iterations")
             .and_then(|x| x.parse().ok())
-            .unwrap_or(std::u64::MAX);
-        let mut cursor = matches.value_of("cursor").map(|x| x.to_string());
+            .unwrap_or(u64::MAX);
+        let cursor = matches.value_of("cursor").map(|x| x.to_string());
+        let parallel = parse_number("parallel", matches.value_of("parallel").unwrap())?;
+        let format = matches.value_of("format").unwrap();
+        let subtitle_format = match format {
+            "json" | "log" | "html" => None,
+            format => Some(format.parse::<subtitles::Format>()?),
+        };
+        let output = matches.value_of("output").map(Path::new);
+        let mut log = LogWriter::new(
+            output,
+            matches.is_present("split-hourly"),
+            matches.is_present("wallclock"),
+        );
+        let options = SubtitleOptions::new(
+            parse_seconds("duration", matches.value_of("duration").unwrap())?,
+            parse_number("max-lines", matches.value_of("max-lines").unwrap())?,
+        )?;
 
-        let mut i = 0;
-        loop {
-            if let Ok(comments) = Comments::new(&vod_id, cursor.as_deref()).await {
-                println!("{}", serde_json::to_string(&comments.comments).unwrap());
-                cursor = comments.cursor.clone();
-                i += 1;
-            } else {
-                break;
+        // Cursors can only be followed one after another, so they're used
+        // only when asked for.
+        let mut pages = if cursor.is_some() || matches.is_present("iterations") {
+            stream::unfold(Some((cursor, 0)), |state| async {
+                let (cursor, i) = state?;
+                if i == iterations {
+                    return None;
+                }
+                match client.comments(vod_id, cursor.as_deref()).await {
+                    Ok(page) => {
+                        let next = page.cursor.map(|cursor| (Some(cursor), i + 1));
+                        Some((Ok(page.comments), next))
+                    }
+                    Err(err) => Some((Err(err), None)),
+                }
+            })
+            .boxed_local()
+        } else {
+            client.all_comments(vod_id, parallel).await?.boxed_local()
+        };
+
+        let mut emotes = None;
+        let mut chat = vec![];
+        while let Some(comments) = pages.next().await {
+            let mut comments = comments?;
+            if matches.is_present("emotes") {
+                if let (None, Some(comment)) = (&emotes, comments.first()) {
+                    let (fetched, errors) =
+                        ThirdPartyEmotes::new(&client, &comment.channel_id).await;
+                    for err in errors {
+                        eprintln!("warning: can't fetch third-party emotes: {}", err);
+                    }
+                    emotes = Some(fetched);
+                }
+                if let Some(emotes) = &emotes {
+                    for comment in &mut comments {
+                        emotes.apply(comment);
+                    }
+                }
             }
+            match format {
+                "json" => println!("{}", serde_json::to_string(&comments)?),
+                "log" => {
+                    for comment in &comments {
+                        log.write(comment)?;
+                    }
+                }
+                _ => chat.extend(comments),
+            }
+        }
+        log.finish()?;
+        let rendered = match subtitle_format {
+            Some(format) => Some(subtitles::render(&chat, format, &options)),
+            None if format == "html" => {
+                let badges = match chat.first() {
+                    Some(comment) => client
+                        .chat_badges(&comment.channel_id)
+                        .await
+                        .unwrap_or_else(|err| {
+                            eprintln!("warning: can't fetch the chat badges: {}", err);
+                            ChatBadges::default()
+                        }),
+                    None => ChatBadges::default(),
+                };
+                let urls = html::image_urls(client.endpoints(), &chat, &badges);
+                let (images, errors) = Images::fetch(&client, &urls).await;
+                if let Some(err) = errors.first() {
+                    eprintln!(
+                        "warning: can't download {} of {} images, e.g.: {}",
+                        errors.len(),
+                        urls.len(),
+                        err
+                    );
+                }
+                Some(html::render(
+                    client.endpoints(),
+                    vod_id,
+                    &chat,
+                    &badges,
+                    &images,
+                ))
+            }
+            None => None,
+        };
+        if let Some(rendered) = rendered {
+            match output {
+                Some(output) => std::fs::write(output, rendered)?,
+                None => print!("{}", rendered),
+            }
+        }
+    }
 
-            if cursor.is_none() || i == iterations {
-                break;
+    Ok(())
+}
+
+/// Downloads a clip to the file given by `template`, unless it already
+/// exists. Returns the path of the file downloaded.
+async fn download_clip(
+    client: &TwitchClient,
+    clip: &Clip,
+    quality: &str,
+    template: &str,
+    retries: u32,
+) -> Result<Option<PathBuf>> {
+    let media = client.clip_media(&clip.id).await?;
+    let quality = media.select_or_err(quality)?;
+    let output = PathBuf::from(clip.file_name(template, quality));
+    if output.exists() {
+        return Ok(None);
+    }
+    if let Some(parent) = output
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+    download::download_file(client, &media.url(quality)?, &output, retries).await?;
+    Ok(Some(output))
+}
+
+fn quality_arg() -> Arg<'static, 'static> {
+    Arg::with_name("quality")
+        .short("q")
+        .long("quality")
+        .value_name("QUALITY")
+        .help("Rendition to use: comma separated list of qualities tried in order, each one of best, worst, source, audio_only, a video group (e.g. 720p60) or the start of a rendition name (e.g. 1080p60)")
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T> {
+    value.parse().map_err(|_| {
+        Error::InvalidArgument(format!(
+            "{} parameter `{}` is not a valid number",
+            name, value
+        ))
+    })
+}
+
+fn format_duration(duration: std::time::Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+fn print_progress(progress: &Progress) {
+    let eta = progress
+        .eta()
+        .map(format_duration)
+        .unwrap_or_else(|| "--:--:--".to_string());
+    eprint!(
+        "\r{}/{} segments, {:.1} MiB, ETA {}",
+        progress.segments_done,
+        progress.segments_total,
+        progress.bytes as f64 / (1024.0 * 1024.0),
+        eta
+    );
+    let _ = std::io::stderr().flush();
+}
+
+/// Resolves on SIGINT or SIGTERM.
+#[cfg(unix)]
+async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
             }
         }
+        Err(_) => {
+            let _ = tokio::signal::ctrl_c().await;
+        }
     }
 }
+
+#[cfg(not(unix))]
+async fn shutdown_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+fn print_record_progress(progress: &RecordProgress) {
+    eprint!(
+        "\r{} segments ({} missed), {:.1} MiB, {}",
+        progress.segments,
+        progress.missed,
+        progress.bytes as f64 / (1024.0 * 1024.0),
+        format_duration(progress.elapsed())
+    );
+    let _ = std::io::stderr().flush();
+}
+
+/// Non-empty, trimmed lines of a file, or of stdin if `path` is `-`.
+fn read_lines(path: &str) -> Result<Vec<String>> {
+    let content = if path == "-" {
+        let mut content = String::new();
+        std::io::Read::read_to_string(&mut std::io::stdin(), &mut content)?;
+        content
+    } else {
+        std::fs::read_to_string(path)?
+    };
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+fn parse_date(name: &str, value: &str) -> Result<i64> {
+    time::parse_rfc3339(value).ok_or_else(|| {
+        Error::InvalidArgument(format!(
+            "{} parameter `{}` is not an RFC 3339 date (e.g. 2020-03-26T00:00:00Z)",
+            name, value
+        ))
+    })
+}
+
+fn parse_seconds(name: &str, value: &str) -> Result<f64> {
+    time::parse_timestamp(value).ok_or_else(|| {
+        Error::InvalidArgument(format!(
+            "{} parameter `{}` is not a valid timestamp",
+            name, value
+        ))
+    })
+}
+
+/// Start and end in seconds of the range of a VOD given by `-s` and `-e`.
+/// The start defaults to `link_start`, the `?t=` of the link to the VOD, and
+/// to the start of the VOD without it.
+fn range(matches: &ArgMatches<'_>, link_start: Option<f64>) -> Result<(Option<f64>, Option<f64>)> {
+    // An explicit -s wins over the `?t=` of a link.
+    let start = match matches.occurrences_of("start") {
+        0 => link_start.or(Some(0.0)),
+        _ => Some(parse_seconds("start", matches.value_of("start").unwrap())?),
+    };
+    let end = matches
+        .value_of("end")
+        .map(|x| parse_seconds("end", x))
+        .transpose()?;
+    Ok((start, end))
+}
@@ -0,0 +1,172 @@
+//! Minimal HTTP server standing in for the Twitch APIs in tests.
+
+use crate::client::{Credentials, HelixCredentials, TwitchClient};
+use crate::config::Endpoints;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+pub struct Route {
+    prefix: String,
+    responses: Vec<(u16, String)>,
+    hits: AtomicUsize,
+}
+
+impl Route {
+    /// Responds with `body` to every request whose path and query start with
+    /// `prefix`. Occurrences of `{base}` in `body` are replaced with the base
+    /// URL of the server.
+    pub fn new(prefix: &str, body: &str) -> Route {
+        Route::with_status(prefix, 200, body)
+    }
+
+    pub fn with_status(prefix: &str, status: u16, body: &str) -> Route {
+        Route::sequence(prefix, &[(status, body)])
+    }
+
+    /// Responds with each of `responses` in turn, repeating the last one once
+    /// all of them have been sent.
+    pub fn sequence(prefix: &str, responses: &[(u16, &str)]) -> Route {
+        Route {
+            prefix: prefix.to_string(),
+            responses: responses
+                .iter()
+                .map(|(status, body)| (*status, body.to_string()))
+                .collect(),
+            hits: AtomicUsize::new(0),
+        }
+    }
+
+    fn respond(&self) -> (u16, &str) {
+        let hit = self.hits.fetch_add(1, Ordering::SeqCst);
+        let (status, body) = &self.responses[hit.min(self.responses.len() - 1)];
+        (*status, body)
+    }
+}
+
+pub struct MockServer {
+    addr: SocketAddr,
+}
+
+impl MockServer {
+    /// Starts serving `routes` on a random local port. Routes are matched in
+    /// order, unmatched requests get a 404.
+    pub async fn start(routes: Vec<Route>) -> MockServer {
+        let mut listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind mock server");
+        let addr = listener.local_addr().unwrap();
+        let base = format!("http://{}", addr);
+        let routes = Arc::new(
+            routes
+                .into_iter()
+                .map(|route| Route {
+                    responses: route
+                        .responses
+                        .into_iter()
+                        .map(|(status, body)| (status, body.replace("{base}", &base)))
+                        .collect(),
+                    ..route
+                })
+                .collect::<Vec<_>>(),
+        );
+
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = match listener.accept().await {
+                    Ok(conn) => conn,
+                    Err(_) => break,
+                };
+                tokio::spawn(handle(socket, routes.clone()));
+            }
+        });
+
+        MockServer { addr }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn endpoints(&self) -> Endpoints {
+        let url = self.url();
+        Endpoints {
+            api: url.clone(),
+            gql: url.clone(),
+            tmi: url.clone(),
+            usher: url.clone(),
+            oauth: format!("{}/oauth2", url),
+            bttv: format!("{}/bttv", url),
+            ffz: format!("{}/ffz", url),
+            seventv: format!("{}/7tv", url),
+            twitch_cdn: format!("{}/twitch-cdn", url),
+            bttv_cdn: format!("{}/bttv-cdn", url),
+            ffz_cdn: format!("{}/ffz-cdn", url),
+            seventv_cdn: format!("{}/7tv-cdn", url),
+        }
+    }
+
+    pub fn client(&self) -> TwitchClient {
+        let credentials = Credentials {
+            helix: Some(HelixCredentials {
+                client_id: "helixclient".to_string(),
+                access_token: "helixtoken".to_string(),
+            }),
+            ..Credentials::default()
+        };
+        TwitchClient::new(self.endpoints(), credentials).expect("Failed to create Twitch client")
+    }
+}
+
+async fn handle(mut socket: TcpStream, routes: Arc<Vec<Route>>) {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 4096];
+    let header_end = loop {
+        match socket.read(&mut buf).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => request.extend_from_slice(&buf[..n]),
+        }
+        if let Some(pos) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&request[..header_end]).to_string();
+    let content_length = head
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, ':');
+            let name = parts.next()?;
+            if name.eq_ignore_ascii_case("content-length") {
+                parts.next()?.trim().parse::<usize>().ok()
+            } else {
+                None
+            }
+        })
+        .next()
+        .unwrap_or(0);
+    while request.len() < header_end + content_length {
+        match socket.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => request.extend_from_slice(&buf[..n]),
+        }
+    }
+
+    let target = head.split_whitespace().nth(1).unwrap_or("/");
+    let (status, body) = routes
+        .iter()
+        .find(|route| target.starts_with(&route.prefix))
+        .map(|route| route.respond())
+        .unwrap_or((404, "Not Found"));
+
+    let response = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = socket.write_all(response.as_bytes()).await;
+    let _ = socket.shutdown(std::net::Shutdown::Write);
+}
@@ -0,0 +1,379 @@
+//! Recording of live streams, following their media playlist.
+
+use crate::api::{fetch_media_playlist, Segment};
+use crate::client::TwitchClient;
+use crate::download::{fetch_segment, is_retryable};
+use crate::error::{Error, Result};
+use crate::hls::MediaPlaylist;
+use futures::future;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::watch;
+
+#[derive(Debug, Clone)]
+pub struct RecordOptions {
+    /// How many times the playlist or a segment is requested again after a
+    /// failure.
+    pub retries: u32,
+    /// Time between two requests of the playlist, defaults to its target
+    /// duration.
+    pub poll_interval: Option<Duration>,
+    /// Media sequence number of the last segment already in the output, to
+    /// resume an interrupted recording of the same broadcast.
+    pub after_sequence: Option<u64>,
+}
+
+impl Default for RecordOptions {
+    fn default() -> RecordOptions {
+        RecordOptions {
+            retries: 5,
+            poll_interval: None,
+            after_sequence: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordProgress {
+    pub bytes: u64,
+    pub segments: usize,
+    /// Segments that expired before they could be fetched, because the
+    /// playlist wasn't polled often enough or they weren't available anymore.
+    pub missed: usize,
+    /// Media sequence number of the last segment written.
+    pub last_sequence: Option<u64>,
+    /// Whether the recording was stopped before the stream ended.
+    pub interrupted: bool,
+    started: Instant,
+}
+
+impl RecordProgress {
+    fn new(last_sequence: Option<u64>) -> RecordProgress {
+        RecordProgress {
+            bytes: 0,
+            segments: 0,
+            missed: 0,
+            last_sequence,
+            interrupted: false,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+/// Fetches the playlist, retrying on network and server errors. Returns
+/// `None` once the playlist is gone, which happens when the stream ends.
+async fn poll_playlist(
+    client: &TwitchClient,
+    url: &str,
+    retries: u32,
+) -> Result<Option<MediaPlaylist>> {
+    let mut attempt = 0;
+    loop {
+        match fetch_media_playlist(client, url).await {
+            Ok(playlist) => return Ok(Some(playlist)),
+            Err(Error::NotFound(_)) => return Ok(None),
+            Err(err) if is_retryable(&err) && attempt < retries => {
+                tokio::time::delay_for(Duration::from_millis(500 * 2u64.pow(attempt))).await;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Resolves once `true` is sent on `stop`, never if its sender is dropped.
+pub(crate) async fn stopped(stop: &mut watch::Receiver<bool>) {
+    if *stop.borrow() {
+        return;
+    }
+    while let Some(value) = stop.recv().await {
+        if value {
+            return;
+        }
+    }
+    future::pending().await
+}
+
+/// Records the live stream of `channel_name` in the rendition matching
+/// `quality`, appending its segments to `output` until the stream ends.
+///
+/// The media playlist is polled every `options.poll_interval` and every
+/// segment is written once, in order of media sequence number, so segments
+/// listed by several consecutive polls aren't duplicated. Recording stops
+/// cleanly when the playlist gets `#EXT-X-ENDLIST` or disappears; if the
+/// channel isn't live to begin with, it fails with `Error::Offline`.
+pub async fn record<F>(
+    client: &TwitchClient,
+    channel_name: &str,
+    quality: &str,
+    output: &Path,
+    options: &RecordOptions,
+    on_progress: F,
+) -> Result<RecordProgress>
+where
+    F: FnMut(&RecordProgress),
+{
+    let (_stop, stop) = watch::channel(false);
+    record_until(
+        client,
+        channel_name,
+        quality,
+        output,
+        options,
+        stop,
+        on_progress,
+    )
+    .await
+}
+
+/// Like `record`, but also stops as soon as `true` is sent on `stop`. The
+/// segment being fetched at that moment is dropped, so that the output only
+/// ever contains whole segments.
+pub async fn record_until<F>(
+    client: &TwitchClient,
+    channel_name: &str,
+    quality: &str,
+    output: &Path,
+    options: &RecordOptions,
+    mut stop: watch::Receiver<bool>,
+    mut on_progress: F,
+) -> Result<RecordProgress>
+where
+    F: FnMut(&RecordProgress),
+{
+    let variant = client.live_variant(channel_name, quality).await?;
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(output)
+        .await?;
+
+    let mut progress = RecordProgress::new(options.after_sequence);
+    on_progress(&progress);
+    while !*stop.borrow() {
+        let playlist = tokio::select! {
+            playlist = poll_playlist(client, &variant.uri, options.retries) => playlist?,
+            _ = stopped(&mut stop) => None,
+        };
+        let playlist = match playlist {
+            Some(playlist) => playlist,
+            None => break,
+        };
+
+        for segment in Segment::from_playlist(&playlist) {
+            let next = progress.last_sequence.map(|last| last + 1).unwrap_or(0);
+            if segment.number < next {
+                continue;
+            }
+            let bytes = tokio::select! {
+                bytes = fetch_segment(client, &segment, options.retries) => bytes,
+                _ = stopped(&mut stop) => break,
+            };
+            if progress.last_sequence.is_some() {
+                progress.missed += (segment.number - next) as usize;
+            }
+            match bytes {
+                Ok(bytes) => {
+                    file.write_all(&bytes).await?;
+                    file.flush().await?;
+                    progress.bytes += bytes.len() as u64;
+                    progress.segments += 1;
+                }
+                Err(Error::NotFound(_)) => progress.missed += 1,
+                Err(err) => return Err(err),
+            }
+            progress.last_sequence = Some(segment.number);
+            on_progress(&progress);
+        }
+
+        if playlist.end_list {
+            break;
+        }
+        let interval = options
+            .poll_interval
+            .unwrap_or_else(|| Duration::from_secs(playlist.target_duration.max(1)));
+        tokio::select! {
+            _ = tokio::time::delay_for(interval) => {}
+            _ = stopped(&mut stop) => {}
+        }
+    }
+    file.flush().await?;
+    progress.interrupted = *stop.borrow();
+    Ok(progress)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockServer, Route};
+    use tokio::runtime::Runtime;
+
+    const ACCESS_TOKEN: &str =
+        r#"{"data":{"streamPlaybackAccessToken":{"value":"{}","signature":"abcdef"}}}"#;
+    const MASTER_PLAYLIST: &str = "#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=6000000,VIDEO=\"chunked\"
+{base}/live/chunked.m3u8
+";
+
+    fn playlist(sequence: u64, segments: &[u64], end_list: bool) -> String {
+        let mut playlist = format!(
+            "#EXTM3U\n#EXT-X-TARGETDURATION:2\n#EXT-X-MEDIA-SEQUENCE:{}\n",
+            sequence
+        );
+        for segment in segments {
+            playlist.push_str(&format!("#EXTINF:2.000,live\n{}.ts\n", segment));
+        }
+        if end_list {
+            playlist.push_str("#EXT-X-ENDLIST\n");
+        }
+        playlist
+    }
+
+    fn record_test(name: &str, playlists: &[(u16, &str)]) -> (Result<RecordProgress>, String) {
+        let output = std::env::temp_dir().join(format!(
+            "twitch-cli-test-{}-{}.ts",
+            std::process::id(),
+            name
+        ));
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let progress = runtime.block_on(async {
+            let server = MockServer::start(vec![
+                Route::new("/gql", ACCESS_TOKEN),
+                Route::new("/api/channel/hls/", MASTER_PLAYLIST),
+                Route::sequence("/live/chunked.m3u8", playlists),
+                Route::new("/live/0.ts", "a"),
+                Route::new("/live/1.ts", "b"),
+                Route::new("/live/2.ts", "c"),
+                Route::new("/live/3.ts", "d"),
+                Route::new("/live/5.ts", "f"),
+            ])
+            .await;
+            let options = RecordOptions {
+                retries: 0,
+                poll_interval: Some(Duration::from_millis(10)),
+                after_sequence: None,
+            };
+            record(
+                &server.client(),
+                "dariomocciatwitch",
+                "best",
+                &output,
+                &options,
+                |_| (),
+            )
+            .await
+        });
+        let content = std::fs::read_to_string(&output).unwrap_or_default();
+        let _ = std::fs::remove_file(&output);
+        (progress, content)
+    }
+
+    #[test]
+    fn record_until_end_list() {
+        let (progress, content) = record_test(
+            "record-end-list",
+            &[
+                (200, &playlist(0, &[0, 1], false)),
+                (200, &playlist(1, &[1, 2], false)),
+                (200, &playlist(1, &[1, 2], false)),
+                (200, &playlist(2, &[2, 3], true)),
+            ],
+        );
+        let progress = progress.unwrap();
+        assert_eq!(content, "abcd");
+        assert_eq!(progress.segments, 4);
+        assert_eq!(progress.missed, 0);
+        assert_eq!(progress.last_sequence, Some(3));
+    }
+
+    #[test]
+    fn record_until_offline() {
+        let (progress, content) = record_test(
+            "record-offline",
+            &[
+                (200, &playlist(0, &[0, 1], false)),
+                (200, &playlist(5, &[5], false)),
+                (404, ""),
+            ],
+        );
+        let progress = progress.unwrap();
+        assert_eq!(content, "abf");
+        assert_eq!(progress.segments, 3);
+        assert_eq!(progress.missed, 3);
+    }
+
+    #[test]
+    fn record_until_stopped() {
+        let output = std::env::temp_dir().join(format!(
+            "twitch-cli-test-{}-record-stopped.ts",
+            std::process::id()
+        ));
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let progress = runtime
+            .block_on(async {
+                let server = MockServer::start(vec![
+                    Route::new("/gql", ACCESS_TOKEN),
+                    Route::new("/api/channel/hls/", MASTER_PLAYLIST),
+                    Route::new("/live/chunked.m3u8", &playlist(3, &[3, 4, 5], false)),
+                    Route::new("/live/", "x"),
+                ])
+                .await;
+                let options = RecordOptions {
+                    after_sequence: Some(3),
+                    ..RecordOptions::default()
+                };
+                let (stop_tx, stop) = watch::channel(false);
+                record_until(
+                    &server.client(),
+                    "dariomocciatwitch",
+                    "best",
+                    &output,
+                    &options,
+                    stop,
+                    |progress| {
+                        if progress.segments > 0 {
+                            let _ = stop_tx.broadcast(true);
+                        }
+                    },
+                )
+                .await
+            })
+            .unwrap();
+        assert!(progress.interrupted);
+        assert_eq!(progress.last_sequence, Some(4));
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "x");
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn record_offline_channel() {
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let output = std::env::temp_dir().join(format!(
+            "twitch-cli-test-{}-record-not-live.ts",
+            std::process::id()
+        ));
+        let progress = runtime.block_on(async {
+            let server = MockServer::start(vec![Route::new("/gql", ACCESS_TOKEN)]).await;
+            let options = RecordOptions::default();
+            record(
+                &server.client(),
+                "dariomocciatwitch",
+                "best",
+                &output,
+                &options,
+                |_| (),
+            )
+            .await
+        });
+        assert!(!output.exists());
+        match progress {
+            Err(Error::Offline(channel)) => assert_eq!(channel, "dariomocciatwitch"),
+            other => panic!("expected Offline, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! Engagement statistics of the chat of VODs.
+
+use crate::api::{Comment, Fragment};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// Words shorter than this aren't counted, which leaves out most articles
+/// and conjunctions.
+const MIN_WORD_LENGTH: usize = 3;
+
+/// Columns of `ChatStats::csv_row`.
+pub const CSV_HEADER: &str = "vod_id,messages,unique_chatters,first_time_chatters,\
+                              subscriber_messages,subscriber_share,unique_subscribers,\
+                              peak_messages_per_minute,average_messages_per_minute,\
+                              top_chatter,top_emote,top_word";
+/// Columns of `ChatStats::csv_minutes`.
+pub const CSV_MINUTES_HEADER: &str = "vod_id,minute,messages";
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Count {
+    pub name: String,
+    pub count: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ChatStats {
+    pub vod_id: String,
+    pub messages: u64,
+    /// Messages sent in every minute of the VOD, from the first one.
+    pub messages_per_minute: Vec<u64>,
+    pub unique_chatters: usize,
+    /// Chatters who didn't chat in any of the VODs seen before.
+    pub first_time_chatters: usize,
+    pub subscriber_messages: u64,
+    /// Share of the messages sent by subscribers, from 0 to 1.
+    pub subscriber_share: f64,
+    pub unique_subscribers: usize,
+    pub top_chatters: Vec<Count>,
+    pub top_emotes: Vec<Count>,
+    pub top_words: Vec<Count>,
+}
+
+/// The `top` names with the highest counts, ties broken by name.
+fn top(counts: HashMap<String, u64>, top: usize) -> Vec<Count> {
+    let mut counts = counts
+        .into_iter()
+        .map(|(name, count)| Count { name, count })
+        .collect::<Vec<_>>();
+    counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+    counts.truncate(top);
+    counts
+}
+
+/// Quotes a CSV field if needed.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+impl ChatStats {
+    /// Statistics of the comments of the VOD with ID `vod_id`, listing the
+    /// `top` chatters, emotes and words. `seen` holds the logins of the
+    /// chatters of the VODs before this one, and gets the ones of this VOD.
+    pub fn new(
+        vod_id: &str,
+        comments: &[Comment],
+        seen: &mut HashSet<String>,
+        top_count: usize,
+    ) -> ChatStats {
+        let mut messages_per_minute = vec![];
+        let mut chatters = HashMap::new();
+        let mut subscribers = HashSet::new();
+        let mut subscriber_messages = 0;
+        let mut emotes = HashMap::new();
+        let mut words = HashMap::new();
+        for comment in comments {
+            let minute = (comment.offset().as_secs() / 60) as usize;
+            if messages_per_minute.len() <= minute {
+                messages_per_minute.resize(minute + 1, 0);
+            }
+            messages_per_minute[minute] += 1;
+
+            let (_, count) = chatters
+                .entry(comment.user.username.as_str())
+                .or_insert((comment.user.display_name.as_str(), 0));
+            *count += 1;
+            if comment.is_subscriber() {
+                subscriber_messages += 1;
+                subscribers.insert(comment.user.username.as_str());
+            }
+
+            let mut text = |text: &str| {
+                for word in text.split_whitespace() {
+                    let word = word.trim_matches(|c: char| !c.is_alphanumeric());
+                    if word.chars().count() >= MIN_WORD_LENGTH {
+                        *words.entry(word.to_lowercase()).or_insert(0) += 1;
+                    }
+                }
+            };
+            if comment.fragments.is_empty() {
+                text(&comment.message);
+            }
+            for fragment in &comment.fragments {
+                match fragment {
+                    Fragment::Text { text: fragment } => text(fragment),
+                    Fragment::Emote { text, .. } => {
+                        *emotes.entry(text.clone()).or_insert(0) += 1;
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        let first_time_chatters = chatters
+            .keys()
+            .filter(|login| seen.insert(login.to_string()))
+            .count();
+        let messages = comments.len() as u64;
+        ChatStats {
+            vod_id: vod_id.to_string(),
+            messages,
+            messages_per_minute,
+            unique_chatters: chatters.len(),
+            first_time_chatters,
+            subscriber_messages,
+            subscriber_share: if messages == 0 {
+                0.0
+            } else {
+                subscriber_messages as f64 / messages as f64
+            },
+            unique_subscribers: subscribers.len(),
+            top_chatters: top(
+                chatters
+                    .into_iter()
+                    .map(|(_, (name, count))| (name.to_string(), count))
+                    .collect(),
+                top_count,
+            ),
+            top_emotes: top(emotes, top_count),
+            top_words: top(words, top_count),
+        }
+    }
+
+    /// Summary of the statistics, with the columns of `CSV_HEADER`.
+    pub fn csv_row(&self) -> String {
+        let peak = self.messages_per_minute.iter().max().copied().unwrap_or(0);
+        let average = if self.messages_per_minute.is_empty() {
+            0.0
+        } else {
+            self.messages as f64 / self.messages_per_minute.len() as f64
+        };
+        let first = |counts: &[Count]| {
+            counts
+                .first()
+                .map(|count| csv_field(&count.name))
+                .unwrap_or_default()
+        };
+        format!(
+            "{},{},{},{},{},{:.4},{},{},{:.2},{},{},{}",
+            csv_field(&self.vod_id),
+            self.messages,
+            self.unique_chatters,
+            self.first_time_chatters,
+            self.subscriber_messages,
+            self.subscriber_share,
+            self.unique_subscribers,
+            peak,
+            average,
+            first(&self.top_chatters),
+            first(&self.top_emotes),
+            first(&self.top_words)
+        )
+    }
+
+    /// Messages of every minute, one row per minute with the columns of
+    /// `CSV_MINUTES_HEADER`.
+    pub fn csv_minutes(&self) -> Vec<String> {
+        self.messages_per_minute
+            .iter()
+            .enumerate()
+            .map(|(minute, messages)| {
+                format!("{},{},{}", csv_field(&self.vod_id), minute, messages)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{test_support, CommentUserBadge, EmoteSource};
+
+    fn comment(offset: f64, name: &str, subscriber: bool, fragments: Vec<Fragment>) -> Comment {
+        let mut comment = test_support::comment()
+            .offset(offset)
+            .name(name)
+            .fragments(fragments)
+            .build();
+        if subscriber {
+            comment.user.badges.push(CommentUserBadge {
+                id: "subscriber".to_string(),
+                version: "12".to_string(),
+            });
+        }
+        comment
+    }
+
+    fn text(text: &str) -> Vec<Fragment> {
+        vec![Fragment::Text {
+            text: text.to_string(),
+        }]
+    }
+
+    #[test]
+    fn chat_stats() {
+        let kappa = vec![
+            Fragment::Text {
+                text: "lol, ".to_string(),
+            },
+            Fragment::Emote {
+                text: "Kappa".to_string(),
+                id: "25".to_string(),
+                source: EmoteSource::Twitch,
+                begin: 5,
+                end: 9,
+            },
+        ];
+        let comments = vec![
+            comment(1.0, "Alice", true, text("Hello chat!")),
+            comment(30.0, "Bob", false, kappa.clone()),
+            comment(150.0, "Alice", true, kappa),
+            comment(170.0, "Carl", false, text("hello, \"world\"")),
+        ];
+        let mut seen = ["bob".to_string()].iter().cloned().collect();
+        let stats = ChatStats::new("1", &comments, &mut seen, 2);
+        assert_eq!(stats.messages, 4);
+        assert_eq!(stats.messages_per_minute, vec![2, 0, 2]);
+        assert_eq!(stats.unique_chatters, 3);
+        assert_eq!(stats.first_time_chatters, 2);
+        assert_eq!(seen.len(), 3);
+        assert_eq!(stats.subscriber_messages, 2);
+        assert_eq!(stats.subscriber_share, 0.5);
+        assert_eq!(stats.unique_subscribers, 1);
+        let count = |name: &str, count| Count {
+            name: name.to_string(),
+            count,
+        };
+        assert_eq!(stats.top_chatters, vec![count("Alice", 2), count("Bob", 1)]);
+        assert_eq!(stats.top_emotes, vec![count("Kappa", 2)]);
+        assert_eq!(stats.top_words, vec![count("hello", 2), count("lol", 2)]);
+        assert_eq!(
+            stats.csv_row(),
+            "1,4,3,2,2,0.5000,1,2,1.33,Alice,Kappa,hello"
+        );
+        assert_eq!(stats.csv_minutes(), vec!["1,0,2", "1,1,0", "1,2,2"]);
+        assert_eq!(CSV_HEADER.split(',').count(), 12);
+
+        let stats = ChatStats::new("2", &comments[..1], &mut seen, 2);
+        assert_eq!(stats.first_time_chatters, 0);
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+    }
+}
@@ -0,0 +1,388 @@
+//! Chat replays as subtitle tracks, to watch a VOD with its chat overlaid.
+
+use crate::api::Comment;
+use crate::error::{Error, Result};
+use std::fmt::Write;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Srt,
+    Vtt,
+    Ass,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Format> {
+        match value {
+            "srt" => Ok(Format::Srt),
+            "vtt" => Ok(Format::Vtt),
+            "ass" => Ok(Format::Ass),
+            _ => Err(Error::InvalidArgument(format!(
+                "`{}` isn't a subtitle format, use srt, vtt or ass",
+                value
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SubtitleOptions {
+    /// Seconds every message stays on screen.
+    pub duration: f64,
+    /// Most messages on screen at once, older ones are dropped first.
+    pub max_lines: usize,
+}
+
+impl SubtitleOptions {
+    /// Fails unless messages stay on screen for some time.
+    pub fn new(duration: f64, max_lines: usize) -> Result<SubtitleOptions> {
+        if duration <= 0.0 || !duration.is_finite() {
+            return Err(Error::InvalidArgument(format!(
+                "duration parameter `{}` must be more than 0 seconds",
+                duration
+            )));
+        }
+        Ok(SubtitleOptions {
+            duration,
+            max_lines,
+        })
+    }
+}
+
+impl Default for SubtitleOptions {
+    fn default() -> SubtitleOptions {
+        SubtitleOptions {
+            duration: 5.0,
+            max_lines: 5,
+        }
+    }
+}
+
+/// Messages on screen between `start` and `end`, oldest first.
+struct Cue<'a> {
+    start: f64,
+    end: f64,
+    comments: &'a [&'a Comment],
+}
+
+/// Splits the chat in cues, one for every change of the messages on screen.
+fn cues<'a>(comments: &'a [&'a Comment], options: &SubtitleOptions) -> Vec<Cue<'a>> {
+    let offsets = comments
+        .iter()
+        .map(|comment| comment.content_offset_seconds.max(0.0))
+        .collect::<Vec<_>>();
+    let mut times = offsets
+        .iter()
+        .flat_map(|offset| vec![*offset, offset + options.duration])
+        .collect::<Vec<_>>();
+    times.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    times.dedup();
+
+    // Since every message stays on screen for the same time, the ones on
+    // screen at any time are consecutive.
+    let (mut first, mut last) = (0, 0);
+    let mut cues: Vec<Cue> = vec![];
+    for window in times.windows(2) {
+        let (start, end) = (window[0], window[1]);
+        while last < offsets.len() && offsets[last] <= start {
+            last += 1;
+        }
+        while first < last && offsets[first] + options.duration <= start {
+            first += 1;
+        }
+        let shown = first.max(last.saturating_sub(options.max_lines.max(1)));
+        if shown == last {
+            continue;
+        }
+        let shown = &comments[shown..last];
+        match cues.last_mut() {
+            // A message expiring while it's hidden by newer ones doesn't change
+            // what's on screen.
+            Some(cue) if cue.end == start && std::ptr::eq(cue.comments, shown) => cue.end = end,
+            _ => cues.push(Cue {
+                start,
+                end,
+                comments: shown,
+            }),
+        }
+    }
+    cues
+}
+
+/// `HH:MM:SS` followed by `separator` and the fraction of a second with
+/// `digits` digits, hours being padded to `hours_width` digits.
+fn timestamp(seconds: f64, separator: char, digits: u32, hours_width: usize) -> String {
+    let scale = 10u64.pow(digits);
+    let total = (seconds * scale as f64).round() as u64;
+    let (whole, fraction) = (total / scale, total % scale);
+    format!(
+        "{:0hours_width$}:{:02}:{:02}{}{:0digits$}",
+        whole / 3600,
+        whole / 60 % 60,
+        whole % 60,
+        separator,
+        fraction,
+        hours_width = hours_width,
+        digits = digits as usize
+    )
+}
+
+/// Color of a user as an ASS `&HBBGGRR`, from Twitch's `#RRGGBB`.
+fn ass_color(color: &str) -> Option<String> {
+    let color = color.strip_prefix('#')?;
+    if color.len() != 6 || !color.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(format!(
+        "&H00{}{}{}",
+        &color[4..6],
+        &color[2..4],
+        &color[0..2]
+    ))
+}
+
+/// Name of the ASS style of a user's color.
+fn ass_style(color: &str) -> String {
+    format!("c{}", color.trim_start_matches('#').to_uppercase())
+}
+
+/// Escaped text of a comment, with emotes between `emote`'s tags.
+fn message(comment: &Comment, escape: impl Fn(&str) -> String, emote: (&str, &str)) -> String {
+    if comment.fragments.is_empty() {
+        return escape(&comment.message);
+    }
+    comment
+        .fragments
+        .iter()
+        .map(|fragment| match fragment {
+            fragment if fragment.is_emote() => {
+                format!("{}{}{}", emote.0, escape(fragment.text()), emote.1)
+            }
+            fragment => escape(fragment.text()),
+        })
+        .collect()
+}
+
+/// Renders the chat of a VOD as a subtitle track. Comments don't need to be
+/// sorted.
+pub fn render(comments: &[Comment], format: Format, options: &SubtitleOptions) -> String {
+    let mut comments = comments.iter().collect::<Vec<_>>();
+    comments.sort_by(|a, b| {
+        a.content_offset_seconds
+            .partial_cmp(&b.content_offset_seconds)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    let cues = cues(&comments, options);
+
+    let mut out = String::new();
+    match format {
+        Format::Srt => {
+            // Players read HTML tags and `{\an8}`-like overrides in SRT too.
+            let escape = |text: &str| {
+                text.replace('<', "‹")
+                    .replace('>', "›")
+                    .replace('{', "(")
+                    .replace('}', ")")
+            };
+            for (i, cue) in cues.iter().enumerate() {
+                let _ = writeln!(
+                    out,
+                    "{}\n{} --> {}",
+                    i + 1,
+                    timestamp(cue.start, ',', 3, 2),
+                    timestamp(cue.end, ',', 3, 2)
+                );
+                for comment in cue.comments {
+                    let _ = writeln!(
+                        out,
+                        "{}: {}",
+                        escape(&comment.user.display_name),
+                        message(comment, escape, ("<i>", "</i>"))
+                    );
+                }
+                out.push('\n');
+            }
+        }
+        Format::Vtt => {
+            out.push_str("WEBVTT\n\n");
+            let escape = |text: &str| {
+                text.replace('&', "&amp;")
+                    .replace('<', "&lt;")
+                    .replace('>', "&gt;")
+            };
+            for cue in &cues {
+                let _ = writeln!(
+                    out,
+                    "{} --> {}",
+                    timestamp(cue.start, '.', 3, 2),
+                    timestamp(cue.end, '.', 3, 2)
+                );
+                for comment in cue.comments {
+                    let _ = writeln!(
+                        out,
+                        "{}: {}",
+                        escape(&comment.user.display_name),
+                        message(comment, escape, ("<i>", "</i>"))
+                    );
+                }
+                out.push('\n');
+            }
+        }
+        Format::Ass => {
+            let mut colors = comments
+                .iter()
+                .filter_map(|comment| comment.user.color.as_deref())
+                .filter(|color| ass_color(color).is_some())
+                .map(|color| color.to_uppercase())
+                .collect::<Vec<_>>();
+            colors.sort();
+            colors.dedup();
+
+            out.push_str(
+                "[Script Info]\nScriptType: v4.00+\nPlayResX: 1280\nPlayResY: 720\n\n\
+                 [V4+ Styles]\n\
+                 Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, \
+                 BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, \
+                 BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n",
+            );
+            let style = |name: &str, color: &str, bold: i32| {
+                format!(
+                    "Style: {},Arial,24,{},&H000000FF,&H00000000,&H80000000,{},0,0,0,100,100,0,0,1,1.5,0,1,20,20,20,1\n",
+                    name, color, bold
+                )
+            };
+            out.push_str(&style("Default", "&H00FFFFFF", 0));
+            for color in &colors {
+                out.push_str(&style(&ass_style(color), &ass_color(color).unwrap(), -1));
+            }
+            out.push_str(
+                "\n[Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, \
+                 Effect, Text\n",
+            );
+
+            // Braces start override blocks and backslashes escapes.
+            let escape = |text: &str| text.replace('\\', "/").replace('{', "(").replace('}', ")");
+            for cue in &cues {
+                let lines = cue
+                    .comments
+                    .iter()
+                    .map(|comment| {
+                        let name = escape(&comment.user.display_name);
+                        let color = comment.user.color.as_deref();
+                        let name = match color.filter(|color| ass_color(color).is_some()) {
+                            Some(color) => format!("{{\\r{}}}{}{{\\r}}", ass_style(color), name),
+                            None => name,
+                        };
+                        let message = message(comment, escape, ("{\\i1}", "{\\i0}"));
+                        format!("{}: {}", name, message)
+                    })
+                    .collect::<Vec<_>>();
+                let _ = writeln!(
+                    out,
+                    "Dialogue: 0,{},{},Default,,0,0,0,,{}",
+                    timestamp(cue.start, '.', 2, 1),
+                    timestamp(cue.end, '.', 2, 1),
+                    lines.join("\\N")
+                );
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::test_support::comment;
+    use crate::api::{EmoteSource, Fragment};
+
+    fn with_emote() -> Comment {
+        comment()
+            .name("Dan")
+            .fragments(vec![
+                Fragment::Text {
+                    text: "a & ".to_string(),
+                },
+                Fragment::Emote {
+                    text: "Kappa".to_string(),
+                    id: "25".to_string(),
+                    source: EmoteSource::Twitch,
+                    begin: 4,
+                    end: 8,
+                },
+            ])
+            .build()
+    }
+
+    fn chat() -> Vec<Comment> {
+        let mut carl = comment()
+            .offset(2.0)
+            .name("Carl")
+            .message("{\\an8}<b>hey</b>")
+            .build();
+        carl.user.color = Some("#00ff7f".to_string());
+        let mut alice = comment().message("hi").build();
+        alice.user.color = Some("#FF0000".to_string());
+        vec![
+            carl,
+            alice,
+            comment().offset(1.0).name("Bob").message("hello").build(),
+        ]
+    }
+
+    #[test]
+    fn srt() {
+        let options = SubtitleOptions::new(3.0, 2).unwrap();
+        assert!(SubtitleOptions::new(0.0, 2).is_err());
+        assert!(SubtitleOptions::new(-1.0, 2).is_err());
+        assert_eq!(
+            render(&chat(), Format::Srt, &options),
+            "1
+00:00:00,000 --> 00:00:01,000
+Alice: hi
+
+2
+00:00:01,000 --> 00:00:02,000
+Alice: hi
+Bob: hello
+
+3
+00:00:02,000 --> 00:00:04,000
+Bob: hello
+Carl: (\\an8)‹b›hey‹/b›
+
+4
+00:00:04,000 --> 00:00:05,000
+Carl: (\\an8)‹b›hey‹/b›
+
+"
+        );
+    }
+
+    #[test]
+    fn vtt() {
+        let vtt = render(&chat(), Format::Vtt, &SubtitleOptions::default());
+        assert!(vtt.starts_with("WEBVTT\n\n00:00:00.000 --> 00:00:01.000\nAlice: hi\n\n"));
+        assert!(vtt.contains("Carl: {\\an8}&lt;b&gt;hey&lt;/b&gt;\n"));
+        let emote = vec![with_emote()];
+        let vtt = render(&emote, Format::Vtt, &SubtitleOptions::default());
+        assert!(vtt.contains("\nDan: a &amp; <i>Kappa</i>\n"));
+    }
+
+    #[test]
+    fn ass() {
+        let ass = render(&chat(), Format::Ass, &SubtitleOptions::default());
+        assert!(ass.contains("\nStyle: c00FF7F,Arial,24,&H007FFF00,"));
+        assert!(ass.contains("\nStyle: cFF0000,Arial,24,&H000000FF,"));
+        assert!(ass.contains(
+            "\nDialogue: 0,0:00:02.00,0:00:05.00,Default,,0,0,0,,\
+             {\\rcFF0000}Alice{\\r}: hi\\NBob: hello\\N{\\rc00FF7F}Carl{\\r}: (/an8)<b>hey</b>\n"
+        ));
+        let emote = vec![with_emote()];
+        let ass = render(&emote, Format::Ass, &SubtitleOptions::default());
+        assert!(ass.contains(",,Dan: a & {\\i1}Kappa{\\i0}\n"));
+    }
+}
@@ -0,0 +1,305 @@
+//! Timestamps accepted by the CLI and returned by Twitch.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Current time in seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Parses a position in a VOD given as seconds (`3723.5`), as `HH:MM:SS` or
+/// `MM:SS` (`1:02:03.5`), or Twitch-style (`1h2m3s`, as in `?t=` links).
+/// Returns `None` if `value` isn't a valid non-negative timestamp.
+pub fn parse_timestamp(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let seconds = if let Ok(seconds) = value.parse::<f64>() {
+        seconds
+    } else if value.contains(':') {
+        parse_clock(value)?
+    } else {
+        parse_units(value, &[('h', 3600.0), ('m', 60.0), ('s', 1.0)])?
+    };
+    if seconds.is_finite() && seconds >= 0.0 {
+        Some(seconds)
+    } else {
+        None
+    }
+}
+
+/// `HH:MM:SS` or `MM:SS`, seconds can have a fractional part.
+fn parse_clock(value: &str) -> Option<f64> {
+    let parts = value.split(':').collect::<Vec<_>>();
+    if parts.len() > 3 {
+        return None;
+    }
+    let (seconds, units) = parts.split_last()?;
+    let seconds = parse_part(seconds)?;
+    if seconds >= 60.0 {
+        return None;
+    }
+    let mut total = seconds;
+    for (i, unit) in units.iter().rev().enumerate() {
+        if unit.is_empty() || !unit.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let unit = unit.parse::<u64>().ok()?;
+        // Minutes are limited to 59 only when hours are given.
+        if i == 0 && units.len() == 2 && unit >= 60 {
+            return None;
+        }
+        total += unit as f64 * 60f64.powi(i as i32 + 1);
+    }
+    Some(total)
+}
+
+/// Parses a duration such as `30d`, `2w` or `1d12h` into seconds.
+pub fn parse_period(value: &str) -> Option<u64> {
+    let units = [
+        ('w', 7.0 * 86400.0),
+        ('d', 86400.0),
+        ('h', 3600.0),
+        ('m', 60.0),
+        ('s', 1.0),
+    ];
+    let seconds = parse_units(value.trim(), &units)?;
+    if seconds > 0.0 {
+        Some(seconds.round() as u64)
+    } else {
+        None
+    }
+}
+
+/// `1h2m3s`, with the units and multipliers of `units`. Every unit is
+/// optional but they must appear in this order.
+fn parse_units(value: &str, units: &[(char, f64)]) -> Option<f64> {
+    let mut total = 0.0;
+    let mut rest = value;
+    let mut units = units.iter();
+    while !rest.is_empty() {
+        let end = rest.find(|c: char| c.is_ascii_alphabetic())?;
+        let number = parse_part(&rest[..end])?;
+        let unit = rest[end..].chars().next()?;
+        let (_, multiplier) = units.find(|(u, _)| *u == unit)?;
+        total += number * multiplier;
+        rest = &rest[end + 1..];
+    }
+    if value.is_empty() {
+        None
+    } else {
+        Some(total)
+    }
+}
+
+fn parse_part(value: &str) -> Option<f64> {
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit() || b == b'.') {
+        return None;
+    }
+    value.parse().ok()
+}
+
+/// Parses an RFC 3339 date (`2020-05-01T18:00:00Z`, as returned by Helix)
+/// into seconds since the Unix epoch. Returns `None` unless every field is in
+/// range, days past the end of their month included.
+pub fn parse_rfc3339(value: &str) -> Option<i64> {
+    let value = value.trim();
+    if value.len() < 20 || !value.is_char_boundary(10) || !value.is_char_boundary(19) {
+        return None;
+    }
+    let (date, rest) = value.split_at(10);
+    let (time, zone) = rest.split_at(9);
+    let mut date = date.split('-');
+    let year = number(date.next()?, 4, 9999)?;
+    let month = number(date.next()?, 2, 12)?;
+    let day = number(date.next()?, 2, days_in_month(year, month))?;
+    if month == 0 || day == 0 {
+        return None;
+    }
+    let time = time.strip_prefix(['T', 't', ' '])?;
+    let mut time = time.split(':');
+    let hours = number(time.next()?, 2, 23)?;
+    let minutes = number(time.next()?, 2, 59)?;
+    // 60 for leap seconds.
+    let seconds = number(time.next()?, 2, 60)?;
+
+    // Fractional seconds are dropped.
+    let zone = match zone.strip_prefix('.') {
+        Some(fraction) => {
+            let zone = fraction.trim_start_matches(|c: char| c.is_ascii_digit());
+            if zone.len() == fraction.len() {
+                return None;
+            }
+            zone
+        }
+        None => zone,
+    };
+    let offset = match zone {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match zone.chars().next()? {
+                '+' => 1,
+                '-' => -1,
+                _ => return None,
+            };
+            let mut zone = zone[1..].split(':');
+            let hours = number(zone.next()?, 2, 23)?;
+            let minutes = number(zone.next()?, 2, 59)?;
+            if zone.next().is_some() {
+                return None;
+            }
+            sign * (hours * 3600 + minutes * 60)
+        }
+    };
+
+    Some(
+        days_from_civil(year, month as u32, day as u32) * 86400
+            + hours * 3600
+            + minutes * 60
+            + seconds
+            - offset,
+    )
+}
+
+/// A field of a date made of exactly `digits` digits, at most `max`.
+fn number(value: &str, digits: usize, max: i64) -> Option<i64> {
+    if value.len() != digits || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok().filter(|value| *value <= max)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Formats seconds since the Unix epoch as an RFC 3339 date in UTC, the
+/// format Helix expects.
+pub fn format_rfc3339(timestamp: i64) -> String {
+    format!("{}Z", format_date_time(timestamp, 'T'))
+}
+
+/// `YYYY-MM-DD HH:MM:SS` in UTC, for people.
+pub fn format_utc(timestamp: i64) -> String {
+    format_date_time(timestamp, ' ')
+}
+
+fn format_date_time(timestamp: i64, separator: char) -> String {
+    let days = timestamp.div_euclid(86400);
+    let seconds = timestamp.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}{}{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        separator,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+/// Days between the Unix epoch and a date of the proleptic Gregorian
+/// calendar, see http://howardhinnant.github.io/date_algorithms.html.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month as u32, day as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(parse_timestamp("3723.5"), Some(3723.5));
+        assert_eq!(parse_timestamp("1:02:03.5"), Some(3723.5));
+        assert_eq!(parse_timestamp("62:03"), Some(3723.0));
+        assert_eq!(parse_timestamp("1h2m3s"), Some(3723.0));
+        assert_eq!(parse_timestamp("2m"), Some(120.0));
+        assert_eq!(parse_timestamp("1h30s"), Some(3630.0));
+        assert_eq!(parse_timestamp("-1"), None);
+        assert_eq!(parse_timestamp("1:60"), None);
+        assert_eq!(parse_timestamp("1:60:00"), None);
+        assert_eq!(parse_timestamp("1:2:3:4"), None);
+        assert_eq!(parse_timestamp("3s2m"), None);
+        assert_eq!(parse_timestamp("1x"), None);
+        assert_eq!(parse_timestamp(""), None);
+    }
+
+    #[test]
+    fn rfc3339() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_rfc3339("2020-05-01T18:00:00Z"), Some(1588356000));
+        assert_eq!(parse_rfc3339("2020-05-01T18:00:00.123Z"), Some(1588356000));
+        assert_eq!(parse_rfc3339("2020-05-01T20:00:00+02:00"), Some(1588356000));
+        assert_eq!(parse_rfc3339("2000-02-29T00:00:00Z"), Some(951782400));
+        assert_eq!(parse_rfc3339("2020-13-01T18:00:00Z"), None);
+        assert_eq!(parse_rfc3339("yesterday"), None);
+        for invalid in &[
+            "2024-02-31T12:00:00Z",
+            "2023-02-29T12:00:00Z",
+            "1900-02-29T12:00:00Z",
+            "2020-04-31T12:00:00Z",
+            "2020-00-10T12:00:00Z",
+            "2020-05-00T12:00:00Z",
+            "2024-02-28T25:99:99Z",
+            "2020-05-01T24:00:00Z",
+            "2020-05-01T18:60:00Z",
+            "2020-05-01T18:00:61Z",
+            "2020-05-01T-1:-1:-1Z",
+            "2020-05-01T18:00:00+24:00",
+            "2020-05-01T18:00:00+02:60",
+            "2020-05-01T18:00:00+-2:00",
+            "2020-05-01T18:00:00.Z",
+            "-020-05-01T18:00:00Z",
+        ] {
+            assert_eq!(parse_rfc3339(invalid), None, "{}", invalid);
+        }
+        assert_eq!(parse_rfc3339("2024-02-29T00:00:00Z"), Some(1709164800));
+        assert_eq!(parse_rfc3339("2016-12-31T23:59:60Z"), Some(1483228800));
+        for timestamp in &[0, 951782400, 1588356000, -86401] {
+            assert_eq!(parse_rfc3339(&format_rfc3339(*timestamp)), Some(*timestamp));
+        }
+        assert_eq!(format_rfc3339(1588356000), "2020-05-01T18:00:00Z");
+        assert_eq!(format_utc(1588356000), "2020-05-01 18:00:00");
+    }
+
+    #[test]
+    fn periods() {
+        assert_eq!(parse_period("30d"), Some(30 * 86400));
+        assert_eq!(parse_period("1w2d"), Some(9 * 86400));
+        assert_eq!(parse_period("12h30m"), Some(45000));
+        assert_eq!(parse_period("0d"), None);
+        assert_eq!(parse_period("2d1w"), None);
+        assert_eq!(parse_period("30"), None);
+    }
+}
@@ -0,0 +1,571 @@
+//! Daemon recording the channels of a watchlist whenever they go live.
+
+use crate::api::Stream;
+use crate::client::TwitchClient;
+use crate::config::{WatchConfig, WatchedChannel};
+use crate::error::{Error, Result};
+use crate::files::sidecar;
+use crate::record::{record_until, stopped, RecordOptions, RecordProgress};
+use crate::time::now;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio::time::Instant;
+
+/// A broadcast `watch` started recording.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Broadcast {
+    pub channel: String,
+    pub output: PathBuf,
+    /// Media sequence number of the last segment written to `output`.
+    pub last_sequence: Option<u64>,
+    /// Whether the broadcast was recorded until its end. Unfinished
+    /// broadcasts are resumed if they are still live when `watch` restarts.
+    pub finished: bool,
+}
+
+/// Broadcasts recorded by `watch`, stored on disk so that a restarted daemon
+/// doesn't record the same broadcast twice.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct WatchState {
+    /// Recorded broadcasts, by broadcast ID.
+    pub broadcasts: BTreeMap<String, Broadcast>,
+}
+
+impl WatchState {
+    /// Path of the state file used when the config doesn't set one.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("twitch-cli").join("watch-state.json"))
+    }
+
+    pub fn load(path: &Path) -> Result<WatchState> {
+        if !path.exists() {
+            return Ok(WatchState::default());
+        }
+        let content = std::fs::read_to_string(path)?;
+        serde_json::from_str(&content).map_err(|err| {
+            Error::Config(format!("invalid watch state `{}`: {}", path.display(), err))
+        })
+    }
+
+    /// Writes the state to a temporary file first, so that a crash can't
+    /// leave a half-written state behind.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = sidecar(path, ".tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Last segment appended to the output of a recording and the size of the
+/// output after it. It's saved right after every segment, next to the
+/// output, so that a resumed recording can drop a segment written after the
+/// last checkpoint instead of writing it twice.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+struct Checkpoint {
+    last_sequence: u64,
+    size: u64,
+}
+
+impl Checkpoint {
+    fn path(output: &Path) -> PathBuf {
+        sidecar(output, ".checkpoint.json")
+    }
+
+    fn load(output: &Path) -> Option<Checkpoint> {
+        let content = std::fs::read_to_string(Self::path(output)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    fn save(&self, output: &Path) -> Result<()> {
+        let path = Self::path(output);
+        let tmp = sidecar(&path, ".tmp");
+        std::fs::write(&tmp, serde_json::to_string(self)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Truncates `output` to the checkpoint.
+    fn restore(&self, output: &Path) -> Result<()> {
+        let file = std::fs::OpenOptions::new().write(true).open(output)?;
+        if file.metadata()?.len() > self.size {
+            file.set_len(self.size)?;
+        }
+        Ok(())
+    }
+}
+
+/// Replaces `{channel}`, `{id}` and `{timestamp}` in the template of a
+/// channel.
+pub fn expand_template(
+    template: &str,
+    channel: &str,
+    broadcast_id: &str,
+    timestamp: u64,
+) -> String {
+    template
+        .replace("{channel}", channel)
+        .replace("{id}", broadcast_id)
+        .replace("{timestamp}", &timestamp.to_string())
+}
+
+#[derive(Debug)]
+pub enum Event {
+    Started {
+        channel: String,
+        output: PathBuf,
+        /// Whether an interrupted recording of the same broadcast is resumed.
+        resumed: bool,
+    },
+    Finished {
+        channel: String,
+        progress: RecordProgress,
+    },
+    Failed {
+        channel: String,
+        error: Error,
+    },
+}
+
+enum Update {
+    Progress(Option<u64>),
+    Done(Result<RecordProgress>),
+}
+
+/// Checks which channels of `config` are live every `config.interval`
+/// seconds, recording every channel as soon as it goes live until its stream
+/// ends. Recordings that fail are resumed at the next check if the channel is
+/// still live.
+///
+/// When `true` is sent on `stop`, the recordings in progress are stopped, the
+/// state is saved and `watch` returns.
+pub async fn watch<F>(
+    client: &TwitchClient,
+    config: &WatchConfig,
+    state_path: &Path,
+    mut stop: watch::Receiver<bool>,
+    mut on_event: F,
+) -> Result<()>
+where
+    F: FnMut(Event),
+{
+    let mut state = WatchState::load(state_path)?;
+    let (updates_tx, mut updates_rx) = mpsc::unbounded_channel();
+    // Names of the channels being recorded.
+    let mut recording = HashSet::new();
+    let interval = Duration::from_secs(config.interval);
+    let mut next_check = Instant::now();
+    let mut stopping = false;
+
+    loop {
+        if !stopping && Instant::now() >= next_check {
+            next_check = Instant::now() + interval;
+            let channels = config
+                .channels
+                .iter()
+                .filter(|channel| !recording.contains(&channel.name))
+                .collect::<Vec<_>>();
+            let names = channels
+                .iter()
+                .map(|channel| channel.name.as_str())
+                .collect::<Vec<_>>();
+            let statuses = client.are_live(&names).await;
+            // Channels that couldn't be checked are checked again next time.
+            let live = channels.into_iter().filter_map(|channel| {
+                let stream = statuses.get(&channel.name)?.stream()?;
+                Some((channel, stream))
+            });
+            for (channel, stream) in live {
+                let started = start(
+                    client,
+                    config,
+                    channel,
+                    stream,
+                    &mut state,
+                    &stop,
+                    &updates_tx,
+                );
+                match started.await {
+                    Ok(Some(event)) => {
+                        state.save(state_path)?;
+                        recording.insert(channel.name.clone());
+                        on_event(event);
+                    }
+                    Ok(None) => (),
+                    Err(error) => on_event(Event::Failed {
+                        channel: channel.name.clone(),
+                        error,
+                    }),
+                }
+            }
+        }
+        if stopping && recording.is_empty() {
+            break;
+        }
+
+        tokio::select! {
+            _ = tokio::time::delay_until(next_check), if !stopping => {}
+            _ = stopped(&mut stop), if !stopping => stopping = true,
+            update = updates_rx.recv() => {
+                let (id, update) = match update {
+                    Some(update) => update,
+                    None => continue,
+                };
+                let broadcast = match state.broadcasts.get_mut(&id) {
+                    Some(broadcast) => broadcast,
+                    None => continue,
+                };
+                match update {
+                    Update::Progress(last_sequence) => broadcast.last_sequence = last_sequence,
+                    Update::Done(result) => {
+                        let channel = broadcast.channel.clone();
+                        recording.remove(&channel);
+                        match result {
+                            Ok(progress) => {
+                                broadcast.finished = !progress.interrupted;
+                                if broadcast.finished {
+                                    let _ = std::fs::remove_file(Checkpoint::path(&broadcast.output));
+                                }
+                                on_event(Event::Finished { channel, progress });
+                            }
+                            Err(error) => on_event(Event::Failed { channel, error }),
+                        }
+                    }
+                }
+                state.save(state_path)?;
+            }
+        }
+    }
+    state.save(state_path)
+}
+
+/// Starts recording `stream`, the current broadcast of `channel`, in the
+/// background, unless it has already been recorded.
+async fn start(
+    client: &TwitchClient,
+    config: &WatchConfig,
+    channel: &WatchedChannel,
+    stream: &Stream,
+    state: &mut WatchState,
+    stop: &watch::Receiver<bool>,
+    updates: &mpsc::UnboundedSender<(String, Update)>,
+) -> Result<Option<Event>> {
+    let id = stream.id.clone();
+    let resumed = match state.broadcasts.get(&id) {
+        Some(broadcast) if broadcast.finished => return Ok(None),
+        Some(_) => true,
+        None => false,
+    };
+    let broadcast = state
+        .broadcasts
+        .entry(id.clone())
+        .or_insert_with(|| Broadcast {
+            channel: channel.name.clone(),
+            output: channel.output_dir.join(expand_template(
+                &channel.template,
+                &channel.name,
+                &id,
+                now(),
+            )),
+            last_sequence: None,
+            finished: false,
+        });
+    if let Some(parent) = broadcast.output.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let client = client.clone();
+    let name = channel.name.clone();
+    let quality = channel
+        .quality
+        .clone()
+        .unwrap_or_else(|| config.quality.clone());
+    let output = broadcast.output.clone();
+    // The checkpoint is saved before the state hears of the segment, so it
+    // can be ahead of it.
+    let mut after_sequence = broadcast.last_sequence;
+    match Checkpoint::load(&output) {
+        Some(checkpoint) if resumed => {
+            checkpoint.restore(&output)?;
+            after_sequence = Some(checkpoint.last_sequence);
+        }
+        _ => (),
+    }
+    let initial_size = tokio::fs::metadata(&output)
+        .await
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+    let options = RecordOptions {
+        after_sequence,
+        ..RecordOptions::default()
+    };
+    let event = Event::Started {
+        channel: channel.name.clone(),
+        output: output.clone(),
+        resumed,
+    };
+    let stop = stop.clone();
+    let updates = updates.clone();
+    tokio::spawn(async move {
+        let result = record_until(
+            &client,
+            &name,
+            &quality,
+            &output,
+            &options,
+            stop,
+            |progress| {
+                if let Some(last_sequence) = progress.last_sequence {
+                    let checkpoint = Checkpoint {
+                        last_sequence,
+                        size: initial_size + progress.bytes,
+                    };
+                    // The state is still updated below if this fails.
+                    let _ = checkpoint.save(&output);
+                }
+                let _ = updates.send((id.clone(), Update::Progress(progress.last_sequence)));
+            },
+        )
+        .await;
+        let _ = updates.send((id, Update::Done(result)));
+    });
+    Ok(Some(event))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockServer, Route};
+    use tokio::runtime::Runtime;
+
+    const ACCESS_TOKEN: &str =
+        r#"{"data":{"streamPlaybackAccessToken":{"value":"{}","signature":"abcdef"}}}"#;
+    const MASTER_PLAYLIST: &str = "#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=6000000,VIDEO=\"chunked\"
+{base}/live/chunked.m3u8
+";
+    const MEDIA_PLAYLIST: &str = "#EXTM3U
+#EXT-X-TARGETDURATION:2
+#EXT-X-MEDIA-SEQUENCE:7
+#EXTINF:2.000,live
+7.ts
+#EXTINF:2.000,live
+8.ts
+#EXT-X-ENDLIST
+";
+
+    fn streams(live: &[(&str, &str)]) -> String {
+        let streams = live
+            .iter()
+            .map(|(id, login)| {
+                format!(
+                    r#"{{"id":"{}","user_id":"1","user_login":"{}","user_name":"{}","game_id":"","game_name":"","type":"live","title":"","viewer_count":1,"started_at":"2020-05-01T18:00:00Z","language":"it","thumbnail_url":"","tags":[]}}"#,
+                    id, login, login
+                )
+            })
+            .collect::<Vec<_>>();
+        format!(r#"{{"data":[{}]}}"#, streams.join(","))
+    }
+
+    #[test]
+    fn templates() {
+        assert_eq!(
+            expand_template(
+                "{channel}/{timestamp}-{id}.ts",
+                "streamer",
+                "42",
+                1588356000
+            ),
+            "streamer/1588356000-42.ts"
+        );
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let path = std::env::temp_dir().join(format!(
+            "twitch-cli-test-{}-watch-state.json",
+            std::process::id()
+        ));
+        let mut state = WatchState::default();
+        state.broadcasts.insert(
+            "42".to_string(),
+            Broadcast {
+                channel: "streamer".to_string(),
+                output: PathBuf::from("streamer-42.ts"),
+                last_sequence: Some(7),
+                finished: false,
+            },
+        );
+        state.save(&path).unwrap();
+        assert_eq!(WatchState::load(&path).unwrap(), state);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(WatchState::load(&path).unwrap(), WatchState::default());
+    }
+
+    #[test]
+    fn records_live_channels_once() {
+        let dir =
+            std::env::temp_dir().join(format!("twitch-cli-test-{}-watch", std::process::id()));
+        let state_path = dir.join("state.json");
+        let mut state = WatchState::default();
+        state.broadcasts.insert(
+            "41".to_string(),
+            Broadcast {
+                channel: "recorded".to_string(),
+                output: dir.join("recorded-41.ts"),
+                last_sequence: Some(100),
+                finished: true,
+            },
+        );
+        state.save(&state_path).unwrap();
+
+        let config = WatchConfig {
+            interval: 0,
+            channels: ["recorded", "live", "offline"]
+                .iter()
+                .map(|name| WatchedChannel {
+                    name: name.to_string(),
+                    output_dir: dir.join(name),
+                    template: "{channel}-{id}.ts".to_string(),
+                    quality: None,
+                })
+                .collect(),
+            ..WatchConfig::default()
+        };
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let events = runtime.block_on(async {
+            let server = MockServer::start(vec![
+                Route::new(
+                    "/helix/streams?user_login=recorded&user_login=live&",
+                    &streams(&[("41", "recorded"), ("42", "live")]),
+                ),
+                // Streams are only requested in batches.
+                Route::new("/helix/streams", r#"{"data":[]}"#),
+                Route::new("/gql", ACCESS_TOKEN),
+                Route::new("/api/channel/hls/live.m3u8", MASTER_PLAYLIST),
+                Route::new("/live/chunked.m3u8", MEDIA_PLAYLIST),
+                Route::new("/live/7.ts", "a"),
+                Route::new("/live/8.ts", "b"),
+            ])
+            .await;
+            let (stop_tx, stop) = watch::channel(false);
+            let mut events = vec![];
+            watch(&server.client(), &config, &state_path, stop, |event| {
+                if let Event::Finished { .. } | Event::Failed { .. } = event {
+                    let _ = stop_tx.broadcast(true);
+                }
+                events.push(event);
+            })
+            .await
+            .map(|_| events)
+        });
+
+        let events = events.unwrap();
+        assert_eq!(events.len(), 2);
+        match &events[0] {
+            Event::Started {
+                channel, resumed, ..
+            } => assert_eq!((channel.as_str(), *resumed), ("live", false)),
+            other => panic!("expected Started, got {:?}", other),
+        }
+        match &events[1] {
+            Event::Finished { progress, .. } => assert_eq!(progress.segments, 2),
+            other => panic!("expected Finished, got {:?}", other),
+        }
+        let output = dir.join("live").join("live-42.ts");
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "ab");
+        let state = WatchState::load(&state_path).unwrap();
+        assert_eq!(
+            state.broadcasts["42"],
+            Broadcast {
+                channel: "live".to_string(),
+                output,
+                last_sequence: Some(8),
+                finished: true,
+            }
+        );
+        assert!(!Checkpoint::path(&state.broadcasts["42"].output).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resumes_from_checkpoint() {
+        let dir = std::env::temp_dir().join(format!(
+            "twitch-cli-test-{}-watch-resume",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let state_path = dir.join("state.json");
+        let output = dir.join("live-42.ts");
+        // The state lags behind the checkpoint, and the daemon stopped
+        // after writing part of a segment it didn't record.
+        let mut state = WatchState::default();
+        state.broadcasts.insert(
+            "42".to_string(),
+            Broadcast {
+                channel: "live".to_string(),
+                output: output.clone(),
+                last_sequence: None,
+                finished: false,
+            },
+        );
+        state.save(&state_path).unwrap();
+        std::fs::write(&output, "ab-partial").unwrap();
+        Checkpoint {
+            last_sequence: 8,
+            size: 2,
+        }
+        .save(&output)
+        .unwrap();
+
+        let config = WatchConfig {
+            interval: 0,
+            channels: vec![WatchedChannel {
+                name: "live".to_string(),
+                output_dir: dir.clone(),
+                template: "{channel}-{id}.ts".to_string(),
+                quality: None,
+            }],
+            ..WatchConfig::default()
+        };
+        let playlist =
+            MEDIA_PLAYLIST.replace("#EXT-X-ENDLIST", "#EXTINF:2.000,live\n9.ts\n#EXT-X-ENDLIST");
+        let mut runtime = Runtime::new().expect("Failed to create tokio runtime");
+        let events = runtime.block_on(async {
+            let server = MockServer::start(vec![
+                Route::new("/helix/streams", &streams(&[("42", "live")])),
+                Route::new("/gql", ACCESS_TOKEN),
+                Route::new("/api/channel/hls/live.m3u8", MASTER_PLAYLIST),
+                Route::new("/live/chunked.m3u8", &playlist),
+                Route::new("/live/9.ts", "c"),
+            ])
+            .await;
+            let (stop_tx, stop) = watch::channel(false);
+            let mut events = vec![];
+            watch(&server.client(), &config, &state_path, stop, |event| {
+                if let Event::Finished { .. } | Event::Failed { .. } = event {
+                    let _ = stop_tx.broadcast(true);
+                }
+                events.push(event);
+            })
+            .await
+            .map(|_| events)
+        });
+
+        let events = events.unwrap();
+        match &events[1] {
+            Event::Finished { progress, .. } => assert_eq!(progress.segments, 1),
+            other => panic!("expected Finished, got {:?}", other),
+        }
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "abc");
+        let state = WatchState::load(&state_path).unwrap();
+        assert_eq!(state.broadcasts["42"].last_sequence, Some(9));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}